@@ -1,24 +1,40 @@
 use crate::{
     proxies::PlayerctldProxy,
-    util::{ResultExt, StreamExt2},
+    stream::StreamExt2,
+    util::{follow_owner, ResultExt},
+    Error,
 };
 use tracing::{debug_span, event, info, Instrument, Level};
 use zbus::{
     export::futures_util::{Stream, StreamExt},
+    names::BusName,
     Connection,
 };
 
+const PLAYERCTLD: &str = "org.mpris.MediaPlayer2.playerctld";
+
+/// Player names ordered by playerctld, most recently active first. While playerctld is not
+/// running an error is reported and the names are followed again once it is back.
 pub async fn active_players(
     conn: &Connection,
-) -> zbus::Result<impl Stream<Item = zbus::Result<Vec<String>>>> {
+) -> crate::Result<impl Stream<Item = crate::Result<Vec<String>>> + 'static> {
     let span = debug_span!("active_players");
     async {
-        let proxy: PlayerctldProxy<'static> = PlayerctldProxy::new(conn).await?;
-        let changes = proxy.receive_player_names_changed().await;
-        info!("connected to playerctld");
-        Ok(changes
-            .then(|event| async move { event.get().await })
-            .with_initial_value(proxy.player_names().await).filter_no_change())
+        let owned = conn.clone();
+        let name = BusName::from_static_str(PLAYERCTLD)?;
+        let stream = follow_owner(conn, name, true, Error::PlayerctldNotRunning, move || {
+            let conn = owned.clone();
+            async move {
+                let proxy: PlayerctldProxy<'static> = PlayerctldProxy::new(&conn).await?;
+                let changes = proxy.receive_player_names_changed().await;
+                info!("connected to playerctld");
+                Ok(changes
+                    .then(|event| async move { Ok(event.get().await?) })
+                    .with_initial_value(proxy.player_names().await.map_err(Error::from)))
+            }
+        })
+        .await?;
+        Ok(stream.filter_no_change())
     }
     .instrument(span.clone())
     .await
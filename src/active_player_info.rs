@@ -1,52 +1,26 @@
-use std::{future::ready, sync::Arc};
+use std::{future::ready, sync::Arc, time::Duration};
 
 use crate::{
     active_player::active_players,
-    player_info::{player_info, PlayerInfo},
-    util::{string_to_static, ResultExt, StreamExt2},
+    player_info::{player_info_with, PlayerInfo, COALESCE_WINDOW},
+    stream::StreamExt2,
+    util::{sleep, ResultExt},
 };
 use tracing::{debug_span, event, info, Instrument, Level};
 use zbus::{
     export::futures_util::{stream::once, Stream, StreamExt},
+    names::BusName,
     Connection,
 };
 
 pub async fn active_player_info(
     conn: Connection,
-) -> zbus::Result<impl Stream<Item = Option<zbus::Result<(Arc<Vec<String>>, PlayerInfo)>>>> {
+) -> crate::Result<impl Stream<Item = Option<crate::Result<(Arc<Vec<String>>, PlayerInfo)>>> + 'static>
+{
     let span = debug_span!("active_player_info");
     async move {
-        let stream = active_players(&conn)
-            .await?
-            .then(move |names| {
-                let conn = conn.clone();
-                async move {
-                    match names {
-                        Ok(names) => {
-                            if names.is_empty() {
-                                info!("no active player");
-                                once(ready(None)).right_stream().right_stream()
-                            } else {
-                                let name: &'static str = string_to_static(names[0].clone());
-                                info!("new active player: {name}");
-                                let info = player_info(name, &conn);
-                                let names = Arc::new(names);
-                                match info.await {
-                                    Ok(info) => {
-                                        info.map(move |i| Some(i.map(|i| (names.clone(), i)))).right_stream()
-                                    }
-                                    Err(e) => once(ready(Some(Err(e)))).left_stream(),
-                                }
-                                .left_stream()
-                                .right_stream()
-                            }
-                        }
-                        Err(e) => once(ready(Some(Err(e)))).left_stream(),
-                    }
-                }
-            })
-            .flatten_newest().filter_no_change();
-        Ok(stream)
+        let names = active_players(&conn).await?;
+        Ok(follow_first_player(conn, names, COALESCE_WINDOW))
     }
     .instrument(span.clone())
     .await
@@ -56,3 +30,58 @@ pub async fn active_player_info(
             .instrument_stream(span)
     })
 }
+
+/// How long an empty list of players has to persist before it is reported. playerctld
+/// briefly reports no players while switching between them.
+const EMPTY_DELAY: Duration = Duration::from_millis(200);
+
+/// Follows the first player of every list of player names, yielding `None` if a list is empty.
+/// Property changes arriving within `window` are combined, see [`player_info_with`].
+pub fn follow_first_player<S>(
+    conn: Connection,
+    names: S,
+    window: Duration,
+) -> impl Stream<Item = Option<crate::Result<(Arc<Vec<String>>, PlayerInfo)>>> + 'static
+where
+    S: Stream<Item = crate::Result<Vec<String>>> + 'static,
+{
+    // see `follow_owner` for why the player is connected inside the inner stream
+    names
+        .map(move |names| {
+            let conn = conn.clone();
+            once(async move {
+                match names {
+                    Ok(names) => {
+                        if names.is_empty() {
+                            info!("no active player");
+                            once(async {
+                                sleep(EMPTY_DELAY).await;
+                                None
+                            })
+                            .right_stream()
+                            .right_stream()
+                        } else {
+                            info!("new active player: {}", names[0]);
+                            let info = match BusName::try_from(names[0].clone()) {
+                                Ok(name) => player_info_with(name, &conn, window).await,
+                                Err(e) => Err(e.into()),
+                            };
+                            let names = Arc::new(names);
+                            match info {
+                                Ok(info) => {
+                                    info.map(move |i| Some(i.map(|i| (names.clone(), i)))).right_stream()
+                                }
+                                Err(e) => once(ready(Some(Err(e)))).left_stream(),
+                            }
+                            .left_stream()
+                            .right_stream()
+                        }
+                    }
+                    Err(e) => once(ready(Some(Err(e)))).left_stream(),
+                }
+            })
+            .flatten()
+        })
+        .flatten_newest()
+        .filter_no_change()
+}
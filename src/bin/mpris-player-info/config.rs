@@ -0,0 +1,55 @@
+#[cfg(feature = "info_base")]
+use std::collections::HashMap;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::dirs;
+
+#[cfg(feature = "hooks")]
+use crate::hooks::Hook;
+#[cfg(feature = "info_base")]
+use crate::info::{cleanup::Rule, players::Override};
+#[cfg(feature = "listenbrainz")]
+use crate::listenbrainz::Settings;
+
+/// Settings of `$XDG_CONFIG_HOME/mpris-player-info/config.toml`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Config {
+    #[cfg(feature = "hooks")]
+    #[serde(rename = "hook")]
+    pub hooks: Vec<Hook>,
+    #[cfg(feature = "listenbrainz")]
+    pub listenbrainz: Option<Settings>,
+    /// Rules applied to the titles shown by the bars, `None` for the default rules.
+    #[cfg(feature = "info_base")]
+    pub cleanup: Option<Vec<Rule>>,
+    /// Names and glyphs of players by player id.
+    #[cfg(feature = "info_base")]
+    #[serde(rename = "player")]
+    pub players: HashMap<String, Override>,
+}
+
+pub fn default_path() -> anyhow::Result<PathBuf> {
+    Ok(dirs::config_dir()?.join("config.toml"))
+}
+
+/// Reads the config from `path`, or from [`default_path`] if it is `None`. A missing
+/// default config file is an empty config.
+pub fn load(path: Option<&Path>) -> anyhow::Result<Config> {
+    let (path, required) = match path {
+        Some(path) => (path.to_path_buf(), true),
+        None => (default_path()?, false),
+    };
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !required => return Ok(Config::default()),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+    };
+    toml::from_str(&content).with_context(|| format!("parsing {}", path.display()))
+}
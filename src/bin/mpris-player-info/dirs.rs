@@ -0,0 +1,48 @@
+//! The XDG base directories of the binary.
+
+use std::{env, path::PathBuf};
+
+use anyhow::anyhow;
+
+const NAME: &str = "mpris-player-info";
+
+/// `$<var>`, or `$HOME/<fallback>` if it is not set.
+fn base_dir(var: &str, fallback: &str) -> anyhow::Result<PathBuf> {
+    if let Some(dir) = env::var_os(var).filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(dir));
+    }
+    let home = env::var_os("HOME").ok_or_else(|| anyhow!("HOME not set"))?;
+    Ok(PathBuf::from(home).join(fallback))
+}
+
+/// `$XDG_CONFIG_HOME/mpris-player-info`
+#[cfg(feature = "config")]
+pub fn config_dir() -> anyhow::Result<PathBuf> {
+    Ok(base_dir("XDG_CONFIG_HOME", ".config")?.join(NAME))
+}
+
+/// `$XDG_DATA_HOME/mpris-player-info`
+#[cfg(feature = "history")]
+pub fn data_dir() -> anyhow::Result<PathBuf> {
+    Ok(base_dir("XDG_DATA_HOME", ".local/share")?.join(NAME))
+}
+
+/// `$XDG_STATE_HOME/mpris-player-info`
+#[cfg(feature = "hide_dbus_server")]
+pub fn state_dir() -> anyhow::Result<PathBuf> {
+    Ok(base_dir("XDG_STATE_HOME", ".local/state")?.join(NAME))
+}
+
+/// `$XDG_DATA_HOME` followed by each of `$XDG_DATA_DIRS`.
+#[cfg(feature = "info_base")]
+pub fn data_dirs() -> Vec<PathBuf> {
+    let data_dirs = env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+    base_dir("XDG_DATA_HOME", ".local/share")
+        .ok()
+        .into_iter()
+        .chain(data_dirs.split(':').map(PathBuf::from))
+        .collect()
+}
@@ -1,13 +1,33 @@
-use std::future::pending;
+use std::{
+    fs::{self, File},
+    future::pending,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
 
 use anyhow::Context;
-use tracing::{Level, info};
+use clap::Args;
+use tracing::{info, warn, Level};
 use tracing_subscriber::{EnvFilter,registry, layer::SubscriberExt, util::SubscriberInitExt};
 use zbus::ConnectionBuilder;
 
-use mpris_dbus::proxies::HideServer;
+use mpris_dbus::proxies::{HideServer, State, HIDDEN_FLAG};
 
-pub fn main() -> anyhow::Result<()> {
+use crate::dirs;
+
+const PATH: &str = "/com/github/robinmarchart/mprisutils";
+
+#[derive(Args, Clone)]
+pub struct Options {
+    /// initial hidden state, overrides the persisted state
+    #[arg(long)]
+    hidden: Option<bool>,
+    /// discard the persisted state
+    #[arg(long)]
+    reset: bool,
+}
+
+pub fn main(options: Options) -> anyhow::Result<()> {
     let level = if cfg!(debug_assertions) {
         Level::DEBUG
     } else {
@@ -17,19 +37,113 @@ pub fn main() -> anyhow::Result<()> {
                 .with_default_directive(level.into())
                 .from_env_lossy())
         .init();
+    let state_file = state_file()?;
+    let mut state = if options.reset {
+        info!("discarding persisted state");
+        State::default()
+    } else {
+        load(&state_file).context("loading persisted state")?
+    };
+    if let Some(hidden) = options.hidden {
+        state.flags.insert(HIDDEN_FLAG.to_string(), hidden);
+    }
+    store(&state_file, &state).context("persisting state")?;
     let rt = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build().context("building tokio runtime")?;
     rt.block_on(async {
-        let server = HideServer::new(false);
-        let _conn = ConnectionBuilder::session().context("choosing session dbus")?
+        let server = HideServer::with_state(state).on_change(move |state| {
+            if let Err(e) = store(&state_file, state) {
+                warn!("Error persisting state: {e}")
+            }
+        });
+        let conn = ConnectionBuilder::session().context("choosing session dbus")?
             .name("com.github.robinmarchart.mprisutils").context("setting dbus name")?
-            .serve_at("/com/github/robinmarchart/mprisutils", server).context("setting server")?
+            .serve_at(PATH, server).context("setting server")?
             .build()
             .await.context("setting up connection")?;
+        let iface = conn.object_server().interface::<_, HideServer>(PATH).await.context("looking up server")?;
+        iface.get().await.attach(iface.signal_context().to_owned());
         info!("connection established");
         loop{
         pending::<()>().await
         }
     })
 }
+
+fn state_file() -> anyhow::Result<PathBuf> {
+    Ok(dirs::state_dir()?.join("state"))
+}
+
+/// Reads the state written by [`store`]. A missing file is an empty state.
+fn load(path: &Path) -> anyhow::Result<State> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(State::default()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut state = State::default();
+    for line in content.lines().filter(|line| !line.is_empty()) {
+        let mut fields = line.splitn(3, '\t');
+        match (fields.next(), fields.next().map(unescape), fields.next()) {
+            (Some("flag"), Some(name), Some(value)) => match value.parse() {
+                Ok(value) => {
+                    state.flags.insert(name, value);
+                }
+                Err(e) => warn!("ignoring flag {name} with invalid value {value:?}: {e}"),
+            },
+            (Some("value"), Some(name), Some(value)) => {
+                state.values.insert(name, unescape(value));
+            }
+            (Some("player"), Some(player), None) => {
+                state.hidden_players.insert(player);
+            }
+            _ => warn!("ignoring malformed state line {line:?}"),
+        }
+    }
+    Ok(state)
+}
+
+/// Replaces the state file by writing to a temporary file and renaming it.
+fn store(path: &Path, state: &State) -> io::Result<()> {
+    let mut content = String::new();
+    for (name, value) in &state.flags {
+        content.push_str(&format!("flag\t{}\t{value}\n", escape(name)));
+    }
+    for (name, value) in &state.values {
+        content.push_str(&format!("value\t{}\t{}\n", escape(name), escape(value)));
+    }
+    for player in &state.hidden_players {
+        content.push_str(&format!("player\t{}\n", escape(player)));
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(tmp, path)
+}
+
+fn escape(str: &str) -> String {
+    str.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
+
+fn unescape(str: &str) -> String {
+    let mut result = String::with_capacity(str.len());
+    let mut chars = str.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some(c) => result.push(c),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
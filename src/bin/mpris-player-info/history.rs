@@ -0,0 +1,678 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    fs::{self, create_dir_all, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    pin::pin,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(feature = "listenbrainz")]
+use anyhow::anyhow;
+use anyhow::Context;
+use clap::{Subcommand, ValueEnum};
+use mpris_dbus::{
+    active_player_info::active_player_info,
+    player_info::PlayerInfo,
+    position::{position, Position},
+    proxies::PlaybackStatus,
+};
+use serde::{Deserialize, Serialize};
+use time::{macros::format_description, OffsetDateTime, UtcOffset};
+use tokio::{
+    select,
+    signal::unix::{signal, SignalKind},
+    spawn,
+    sync::mpsc::{unbounded_channel, UnboundedSender},
+    task::JoinHandle,
+};
+use tracing::{info, warn, Level};
+use tracing_subscriber::EnvFilter;
+use zbus::{export::futures_util::StreamExt, names::BusName, Connection};
+
+use crate::dirs;
+#[cfg(feature = "listenbrainz")]
+use crate::{config, listenbrainz};
+
+#[derive(Subcommand, Clone)]
+pub enum Action {
+    ///record the plays of the active player
+    Record {
+        ///config file with the ListenBrainz settings, defaults to
+        ///$XDG_CONFIG_HOME/mpris-player-info/config.toml
+        #[cfg(feature = "listenbrainz")]
+        #[arg(short, long)]
+        config: Option<PathBuf>,
+    },
+    ///list the most recent plays
+    List {
+        ///number of plays to list
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+    },
+    ///count the plays and the time listened
+    Stats {
+        ///what to group the plays by
+        #[arg(long, value_enum, default_value_t = Group::Artist)]
+        by: Group,
+        ///number of groups to show, the most played ones for artists and the latest days
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+    },
+    #[cfg(feature = "listenbrainz")]
+    ///submit the listens spooled while ListenBrainz was unreachable
+    Submit {
+        ///config file with the ListenBrainz settings, defaults to
+        ///$XDG_CONFIG_HOME/mpris-player-info/config.toml
+        #[arg(short, long)]
+        config: Option<PathBuf>,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum Group {
+    Artist,
+    Day,
+}
+
+/// Tracks shorter than this are never recorded.
+const MIN_LENGTH: Duration = Duration::from_secs(30);
+/// A track counts as played after half of its length or this long, whichever comes first.
+const MAX_THRESHOLD: Duration = Duration::from_secs(4 * 60);
+/// Going back this close to the start of a track that counts as played starts a new play,
+/// e.g. when the track repeats.
+const RESTART: Duration = Duration::from_secs(5);
+
+/// A line of the history file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Play {
+    /// Start of the play in seconds since the unix epoch.
+    pub time: u64,
+    pub player: String,
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+    /// Length of the track in seconds, if the player reported it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<f64>,
+    /// Seconds the track was playing.
+    pub listened: f64,
+}
+
+/// Item of [`active_player_info`].
+type Info = Option<mpris_dbus::Result<(Arc<Vec<String>>, PlayerInfo)>>;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Track {
+    player: String,
+    trackid: Option<String>,
+    title: String,
+    artist: Option<String>,
+    album: Option<String>,
+    length: Option<Duration>,
+}
+
+impl Track {
+    /// The track of the active player and wether it is playing, `None` without a title.
+    fn new(info: &Info) -> Option<(Self, bool)> {
+        let (players, info): &(_, PlayerInfo) = info.as_ref()?.as_ref().ok()?;
+        let length = info
+            .metadata_text("mpris:length")
+            .and_then(|length| length.parse().ok())
+            .map(Duration::from_micros);
+        let track = Track {
+            player: players.first()?.clone(),
+            trackid: info.metadata_text("mpris:trackid"),
+            title: info
+                .metadata_text("xesam:title")
+                .filter(|title| !title.is_empty())?,
+            artist: info.metadata_text("xesam:artist"),
+            album: info.metadata_text("xesam:album"),
+            length: length.filter(|length| !length.is_zero()),
+        };
+        Some((track, info.playback_status == PlaybackStatus::Playing))
+    }
+
+    /// Wether both are the same track, players sometimes report the length late.
+    fn same(&self, other: &Track) -> bool {
+        Track {
+            length: None,
+            ..self.clone()
+        } == Track {
+            length: None,
+            ..other.clone()
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Current {
+    track: Track,
+    started: SystemTime,
+    /// Time played before `playing_since`.
+    listened: Duration,
+    playing_since: Option<Instant>,
+}
+
+impl Current {
+    fn new(track: Track, now: Instant, wall: SystemTime, playing: bool) -> Self {
+        Self {
+            track,
+            started: wall,
+            listened: Duration::ZERO,
+            playing_since: playing.then_some(now),
+        }
+    }
+
+    fn listened(&self, now: Instant) -> Duration {
+        self.listened
+            + self
+                .playing_since
+                .map_or(Duration::ZERO, |since| now.saturating_duration_since(since))
+    }
+
+    fn set_playing(&mut self, now: Instant, playing: bool) {
+        match (self.playing_since, playing) {
+            (None, true) => self.playing_since = Some(now),
+            (Some(_), false) => {
+                self.listened = self.listened(now);
+                self.playing_since = None;
+            }
+            _ => {}
+        }
+    }
+
+    /// The play if the track was played long enough to count.
+    fn play(&self, now: Instant) -> Option<Play> {
+        let threshold = match self.track.length {
+            Some(length) if length < MIN_LENGTH => return None,
+            Some(length) => (length / 2).min(MAX_THRESHOLD),
+            None => MAX_THRESHOLD,
+        };
+        let listened = self.listened(now);
+        (listened >= threshold).then(|| Play {
+            time: self
+                .started
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            player: self.track.player.clone(),
+            title: self.track.title.clone(),
+            artist: self.track.artist.clone(),
+            album: self.track.album.clone(),
+            length: self.track.length.map(|length| length.as_secs_f64()),
+            listened: listened.as_secs_f64(),
+        })
+    }
+}
+
+/// The bus names of all players of an item, `None` if they are unknown.
+fn players(info: &Info) -> Option<&[String]> {
+    match info {
+        Some(Ok((players, _))) => Some(players),
+        Some(Err(_)) => None,
+        None => Some(&[]),
+    }
+}
+
+/// Turns the player state into plays. The play of every player is kept until its track
+/// changes, playback restarts, the player vanishes or recording stops, so switching the
+/// active player back and forth continues the plays.
+#[derive(Debug, Default)]
+struct Tracker {
+    /// The current play by bus name of the player.
+    current: HashMap<String, Current>,
+}
+
+impl Tracker {
+    /// Handles a new state of the active player, the first of `players`. Players that are not
+    /// active count as paused.
+    fn update(
+        &mut self,
+        now: Instant,
+        wall: SystemTime,
+        players: Option<&[String]>,
+        track: Option<(Track, bool)>,
+    ) -> Vec<Play> {
+        let mut finished = Vec::new();
+        if let Some(players) = players {
+            let active = players.first().filter(|_| track.is_none());
+            self.current.retain(|player, current| {
+                let keep = players.contains(player) && Some(player) != active;
+                if !keep {
+                    finished.extend(current.play(now));
+                }
+                keep
+            });
+            // only the active player is followed, the others count as paused until they are
+            // active again
+            let active = track.as_ref().map(|(track, _)| &track.player);
+            for (player, current) in &mut self.current {
+                if Some(player) != active {
+                    current.set_playing(now, false);
+                }
+            }
+        }
+        if let Some((track, playing)) = track {
+            match self.current.get_mut(&track.player) {
+                Some(current) if current.track.same(&track) => {
+                    current.set_playing(now, playing);
+                    current.track = track;
+                }
+                _ => {
+                    let current = Current::new(track, now, wall, playing);
+                    let previous = self.current.insert(current.track.player.clone(), current);
+                    finished.extend(previous.and_then(|previous| previous.play(now)));
+                }
+            }
+        }
+        finished.sort_by_key(|play| play.time);
+        finished
+    }
+
+    /// Handles a new position of the player `player`.
+    fn position(
+        &mut self,
+        now: Instant,
+        wall: SystemTime,
+        player: &str,
+        position: Duration,
+    ) -> Option<Play> {
+        let current = self.current.get_mut(player)?;
+        if position >= RESTART {
+            return None;
+        }
+        let play = current.play(now)?;
+        let playing = current.playing_since.is_some();
+        *current = Current::new(current.track.clone(), now, wall, playing);
+        Some(play)
+    }
+
+    /// Finishes the plays of all players.
+    fn finish(&mut self, now: Instant) -> Vec<Play> {
+        let mut finished: Vec<Play> = self
+            .current
+            .drain()
+            .filter_map(|(_, current)| current.play(now))
+            .collect();
+        finished.sort_by_key(|play| play.time);
+        finished
+    }
+}
+
+fn history_file() -> anyhow::Result<PathBuf> {
+    Ok(dirs::data_dir()?.join("history.jsonl"))
+}
+
+fn store(path: &Path, play: &Play) -> anyhow::Result<()> {
+    info!("recording {play:?}");
+    if let Some(dir) = path.parent() {
+        create_dir_all(dir).context("creating data dir")?;
+    }
+    let mut line = serde_json::to_string(play).context("serializing play")?;
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .with_context(|| format!("writing {}", path.display()))
+}
+
+/// Reads the plays of the history file, skipping malformed lines.
+fn load(path: &Path) -> anyhow::Result<Vec<Play>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+    };
+    Ok(content
+        .lines()
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(play) => Some(play),
+            Err(e) => {
+                warn!("skipping malformed history line {line:?}: {e}");
+                None
+            }
+        })
+        .collect())
+}
+
+/// Follows the position of `player`, sending it tagged with the player to `positions`.
+fn follow_position(
+    conn: Connection,
+    player: String,
+    positions: UnboundedSender<(String, Position)>,
+) -> JoinHandle<()> {
+    spawn(async move {
+        let stream = match BusName::try_from(player.clone()) {
+            Ok(name) => position(name, &conn).await,
+            Err(e) => Err(e.into()),
+        };
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("could not follow the position of {player}: {e}");
+                return;
+            }
+        };
+        let mut stream = pin!(stream);
+        while let Some(position) = stream.next().await {
+            if let Ok(position) = position {
+                if positions.send((player.clone(), position)).is_err() {
+                    return;
+                }
+            }
+        }
+    })
+}
+
+async fn record(
+    path: PathBuf,
+    #[cfg(feature = "listenbrainz")] listenbrainz: Option<listenbrainz::Settings>,
+) -> anyhow::Result<()> {
+    #[cfg(feature = "listenbrainz")]
+    let scrobbler = match listenbrainz {
+        Some(settings) => Some(listenbrainz::Scrobbler::start(
+            settings,
+            listenbrainz::Spool::new(listenbrainz::Spool::default_path()?),
+            Arc::new(listenbrainz::Ureq::default()),
+        )),
+        None => None,
+    };
+    // a failed write loses a single play, recording goes on
+    let save = |play: &Play| {
+        if let Err(e) = store(&path, play) {
+            warn!("could not save the play of {:?}: {e:#}", play.title);
+        }
+        #[cfg(feature = "listenbrainz")]
+        if let Some(scrobbler) = &scrobbler {
+            if let Err(e) = scrobbler.listen(play) {
+                warn!("could not submit the play of {:?}: {e:#}", play.title);
+            }
+        }
+    };
+    let conn = Connection::session()
+        .await
+        .context("connecting to session dbus")?;
+    let mut info = pin!(active_player_info(conn.clone())
+        .await
+        .context("following the active player")?);
+    let mut terminate = signal(SignalKind::terminate()).context("handling SIGTERM")?;
+    let mut interrupt = signal(SignalKind::interrupt()).context("handling SIGINT")?;
+    // the position is followed in a task of its own, so it can be replaced on player changes
+    // without leaving pending calls of the info stream unpolled
+    let (sender, mut positions) = unbounded_channel();
+    let mut following: Option<(String, JoinHandle<()>)> = None;
+    let mut tracker = Tracker::default();
+    loop {
+        let plays = select! {
+            item = info.next() => {
+                let Some(item) = item else { break };
+                let track = Track::new(&item);
+                let player = track.as_ref().map(|(track, _)| track.player.clone());
+                if following.as_ref().map(|(player, _)| player) != player.as_ref() {
+                    if let Some((_, task)) = following.take() {
+                        task.abort();
+                    }
+                    following = player.map(|player| {
+                        let task = follow_position(conn.clone(), player.clone(), sender.clone());
+                        (player, task)
+                    });
+                }
+                tracker.update(Instant::now(), SystemTime::now(), players(&item), track)
+            }
+            Some((player, position)) = positions.recv() => {
+                tracker
+                    .position(position.at, SystemTime::now(), &player, position.position)
+                    .into_iter()
+                    .collect()
+            }
+            _ = terminate.recv() => break,
+            _ = interrupt.recv() => break,
+        };
+        for play in plays {
+            save(&play);
+        }
+    }
+    for play in tracker.finish(Instant::now()) {
+        save(&play);
+    }
+    Ok(())
+}
+
+fn local_time(time: u64, offset: UtcOffset) -> OffsetDateTime {
+    OffsetDateTime::from_unix_timestamp(time as i64)
+        .unwrap_or(OffsetDateTime::UNIX_EPOCH)
+        .to_offset(offset)
+}
+
+fn hours(seconds: f64) -> String {
+    let minutes = (seconds / 60.0).round() as u64;
+    format!("{}:{:02}", minutes / 60, minutes % 60)
+}
+
+/// Number of plays and seconds listened per group.
+fn stats(plays: &[Play], by: Group, offset: UtcOffset) -> Vec<(String, usize, f64)> {
+    let mut groups: BTreeMap<String, (usize, f64)> = BTreeMap::new();
+    for play in plays {
+        let key = match by {
+            Group::Artist => play.artist.clone().unwrap_or_else(|| "unknown".to_string()),
+            Group::Day => local_time(play.time, offset).date().to_string(),
+        };
+        let group = groups.entry(key).or_default();
+        group.0 += 1;
+        group.1 += play.listened;
+    }
+    let mut groups: Vec<_> = groups
+        .into_iter()
+        .map(|(key, (plays, listened))| (key, plays, listened))
+        .collect();
+    match by {
+        Group::Artist => groups.sort_by_key(|(key, plays, _)| (Reverse(*plays), key.clone())),
+        Group::Day => groups.reverse(),
+    }
+    groups
+}
+
+pub fn main(action: Action) -> anyhow::Result<()> {
+    let path = history_file()?;
+    // the local offset can only be determined while the process has a single thread
+    let offset = UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC);
+    let level = if cfg!(debug_assertions) {
+        Level::DEBUG
+    } else {
+        Level::INFO
+    };
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::builder()
+                .with_default_directive(level.into())
+                .from_env_lossy(),
+        )
+        .with_writer(io::stderr)
+        .init();
+    match action {
+        Action::Record {
+            #[cfg(feature = "listenbrainz")]
+            config,
+        } => {
+            #[cfg(feature = "listenbrainz")]
+            let listenbrainz = config::load(config.as_deref())
+                .context("loading config")?
+                .listenbrainz;
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .context("building tokio runtime")?
+                .block_on(record(
+                    path,
+                    #[cfg(feature = "listenbrainz")]
+                    listenbrainz,
+                ))
+        }
+        Action::List { limit } => {
+            let plays = load(&path)?;
+            let format = format_description!("[year]-[month]-[day] [hour]:[minute]");
+            for play in plays.iter().rev().take(limit).rev() {
+                let time = local_time(play.time, offset)
+                    .format(format)
+                    .context("formatting time")?;
+                match &play.artist {
+                    Some(artist) => println!("{time}  {artist} - {}", play.title),
+                    None => println!("{time}  {}", play.title),
+                }
+            }
+            Ok(())
+        }
+        Action::Stats { by, limit } => {
+            let plays = load(&path)?;
+            for (key, plays, listened) in stats(&plays, by, offset).into_iter().take(limit) {
+                println!("{plays:>5}  {:>7}  {key}", hours(listened));
+            }
+            Ok(())
+        }
+        #[cfg(feature = "listenbrainz")]
+        Action::Submit { config } => {
+            let settings = config::load(config.as_deref())
+                .context("loading config")?
+                .listenbrainz
+                .ok_or_else(|| anyhow!("no [listenbrainz] section in the config"))?;
+            let spool = listenbrainz::Spool::new(listenbrainz::Spool::default_path()?);
+            let spooled = spool.len()?;
+            let submitted = spool.submit(&listenbrainz::Ureq::default(), &settings)?;
+            println!("submitted {submitted} of {spooled} spooled listens");
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant, SystemTime};
+
+    use time::UtcOffset;
+
+    use super::{stats, Group, Play, Track, Tracker};
+
+    const PLAYER: &str = "org.mpris.MediaPlayer2.test";
+
+    fn track(title: &str, length: u64) -> Track {
+        Track {
+            player: PLAYER.to_string(),
+            trackid: None,
+            title: title.to_string(),
+            artist: Some("A".to_string()),
+            album: None,
+            length: Some(Duration::from_secs(length)),
+        }
+    }
+
+    #[test]
+    fn tracker() {
+        let start = Instant::now();
+        let at = |seconds| start + Duration::from_secs(seconds);
+        let wall = SystemTime::now();
+        let players = [PLAYER.to_string()];
+        let mut tracker = Tracker::default();
+        let mut update = |seconds, track: Option<(Track, bool)>| {
+            let players = track.as_ref().map_or(&[][..], |_| &players[..]);
+            tracker.update(at(seconds), wall, Some(players), track)
+        };
+        assert_eq!(Vec::<Play>::new(), update(0, Some((track("a", 200), true))));
+        // pausing stops counting
+        assert_eq!(
+            Vec::<Play>::new(),
+            update(60, Some((track("a", 200), false)))
+        );
+        assert_eq!(
+            Vec::<Play>::new(),
+            update(500, Some((track("a", 200), true)))
+        );
+        // 60 + 30 seconds of 200 are not enough
+        assert_eq!(
+            Vec::<Play>::new(),
+            update(530, Some((track("b", 600), true)))
+        );
+        // long tracks count after 4 minutes
+        let plays = update(770, Some((track("c", 20), true)));
+        let plays: Vec<_> = plays
+            .iter()
+            .map(|play| (play.title.as_str(), play.listened))
+            .collect();
+        assert_eq!(vec![("b", 240.0)], plays);
+        // short tracks never count
+        assert_eq!(Vec::<Play>::new(), update(800, None));
+    }
+
+    #[test]
+    fn switch() {
+        let start = Instant::now();
+        let at = |seconds| start + Duration::from_secs(seconds);
+        let wall = SystemTime::now();
+        let other = Track {
+            player: "org.mpris.MediaPlayer2.other".to_string(),
+            ..track("b", 200)
+        };
+        let both = [PLAYER.to_string(), other.player.clone()];
+        let mut tracker = Tracker::default();
+        tracker.update(at(0), wall, Some(&both), Some((track("a", 200), true)));
+        // another player becoming active briefly pauses the play instead of ending it
+        let (first, second) = (&both[..1], [both[1].clone(), both[0].clone()]);
+        tracker.update(at(60), wall, Some(&second), Some((other.clone(), true)));
+        assert!(tracker.update(at(70), wall, None, None).is_empty());
+        tracker.update(at(80), wall, Some(&both), Some((track("a", 200), true)));
+        // the other player vanishing ends its play
+        let plays = tracker.update(at(120), wall, Some(first), Some((track("a", 200), true)));
+        assert!(plays.is_empty());
+        let plays = tracker.finish(at(120));
+        let plays: Vec<_> = plays
+            .iter()
+            .map(|play| (play.title.as_str(), play.listened))
+            .collect();
+        assert_eq!(vec![("a", 100.0)], plays);
+    }
+
+    #[test]
+    fn repeat() {
+        let start = Instant::now();
+        let at = |seconds| start + Duration::from_secs(seconds);
+        let wall = SystemTime::now();
+        let mut tracker = Tracker::default();
+        let players = [PLAYER.to_string()];
+        tracker.update(at(0), wall, Some(&players), Some((track("a", 100), true)));
+        // seeking back before half of the track continues the play
+        assert_eq!(None, tracker.position(at(20), wall, PLAYER, Duration::ZERO));
+        assert!(tracker
+            .position(at(60), wall, PLAYER, Duration::from_secs(1))
+            .is_some());
+        assert!(tracker.finish(at(70)).is_empty());
+    }
+
+    #[test]
+    fn artist_stats() {
+        let play = |artist: &str, listened| Play {
+            time: 0,
+            player: String::new(),
+            title: String::new(),
+            artist: Some(artist.to_string()),
+            album: None,
+            length: None,
+            listened,
+        };
+        let plays = [play("a", 10.0), play("b", 10.0), play("b", 20.0)];
+        assert_eq!(
+            vec![("b".to_string(), 2, 30.0), ("a".to_string(), 1, 10.0)],
+            stats(&plays, Group::Artist, UtcOffset::UTC)
+        );
+        assert_eq!(
+            vec![("1970-01-01".to_string(), 3, 40.0)],
+            stats(&plays, Group::Day, UtcOffset::UTC)
+        );
+    }
+}
@@ -0,0 +1,440 @@
+use std::{
+    path::PathBuf,
+    pin::pin,
+    process::Stdio,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use clap::Args;
+use mpris_dbus::{
+    active_player_info::active_player_info,
+    hide::{hidden, HiddenInfo},
+    player_info::PlayerInfo,
+    proxies::PlaybackStatus,
+    stream::{combine_latest, Termination},
+};
+use serde::{Deserialize, Deserializer};
+use tokio::{process::Command, spawn, time::timeout};
+use tracing::{debug, info, warn, Level};
+use tracing_subscriber::EnvFilter;
+use zbus::{export::futures_util::StreamExt, Connection};
+
+use crate::config;
+
+#[derive(Args, Clone)]
+pub struct Options {
+    /// config file with the hooks, defaults to $XDG_CONFIG_HOME/mpris-player-info/config.toml
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EventKind {
+    TrackChanged,
+    StartedPlaying,
+    Paused,
+    Stopped,
+    PlayerAdded,
+    PlayerRemoved,
+    HiddenToggled,
+}
+
+impl EventKind {
+    fn name(self) -> &'static str {
+        match self {
+            EventKind::TrackChanged => "track-changed",
+            EventKind::StartedPlaying => "started-playing",
+            EventKind::Paused => "paused",
+            EventKind::Stopped => "stopped",
+            EventKind::PlayerAdded => "player-added",
+            EventKind::PlayerRemoved => "player-removed",
+            EventKind::HiddenToggled => "hidden-toggled",
+        }
+    }
+}
+
+fn seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    Duration::try_from_secs_f64(f64::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+/// A `[[hook]]` of the config file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Hook {
+    /// Events that run the hook.
+    events: Vec<EventKind>,
+    /// Shell command, the event data is passed in `MPRIS_*` environment variables.
+    command: String,
+    /// Seconds after which the command is killed.
+    #[serde(default = "default_timeout", deserialize_with = "seconds")]
+    timeout: Duration,
+    /// Minimum number of seconds between two runs, events in between are dropped.
+    #[serde(default, deserialize_with = "seconds")]
+    min_interval: Duration,
+}
+
+/// What the events are derived from.
+#[derive(Debug, Clone, Default)]
+struct State {
+    players: Arc<Vec<String>>,
+    player: Option<PlayerInfo>,
+    hidden: Option<bool>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Event {
+    kind: EventKind,
+    /// The added or removed player, the active one for the other events.
+    player: Option<String>,
+}
+
+impl State {
+    /// The state after an update of [`combine_latest`]. Errors and streams that did not yield
+    /// yet keep the previous values.
+    fn update(
+        &self,
+        info: Option<HiddenInfo>,
+        hidden: Option<mpris_dbus::Result<bool>>,
+    ) -> Self {
+        let mut state = self.clone();
+        match info {
+            Some(Some(Ok((players, info)))) => {
+                state.players = players;
+                state.player = Some(info);
+            }
+            Some(None) => {
+                state.players = Default::default();
+                state.player = None;
+            }
+            Some(Some(Err(e))) => debug!("keeping the player state: {e}"),
+            None => {}
+        }
+        match hidden {
+            Some(Ok(hidden)) => state.hidden = Some(hidden),
+            Some(Err(e)) => debug!("keeping the hidden state: {e}"),
+            None => {}
+        }
+        state
+    }
+
+    fn active(&self) -> Option<&str> {
+        self.player
+            .as_ref()
+            .and(self.players.first().map(String::as_str))
+    }
+
+    fn status(&self) -> Option<PlaybackStatus> {
+        self.player.as_ref().map(|info| info.playback_status)
+    }
+
+    fn track(&self) -> Option<[Option<String>; 5]> {
+        let info = self.player.as_ref()?;
+        let track = [
+            self.active().map(str::to_string),
+            info.metadata_text("mpris:trackid"),
+            info.metadata_text("xesam:title"),
+            info.metadata_text("xesam:artist"),
+            info.metadata_text("xesam:album"),
+        ];
+        track[1..].iter().any(Option::is_some).then_some(track)
+    }
+
+    /// The events of the change from `self` to `next`.
+    fn events(&self, next: &State) -> Vec<Event> {
+        let mut events = Vec::new();
+        let player_event = |kind, player: &String| Event {
+            kind,
+            player: Some(player.clone()),
+        };
+        let active_event = |kind| Event {
+            kind,
+            player: next.active().map(str::to_string),
+        };
+        for player in self.players.iter() {
+            if !next.players.contains(player) {
+                events.push(player_event(EventKind::PlayerRemoved, player));
+            }
+        }
+        for player in next.players.iter() {
+            if !self.players.contains(player) {
+                events.push(player_event(EventKind::PlayerAdded, player));
+            }
+        }
+        let track = next.track();
+        if track.is_some() && track != self.track() {
+            events.push(active_event(EventKind::TrackChanged));
+        }
+        let status = next.status();
+        if status != self.status() {
+            match status {
+                Some(PlaybackStatus::Playing) => {
+                    events.push(active_event(EventKind::StartedPlaying))
+                }
+                Some(PlaybackStatus::Paused) => events.push(active_event(EventKind::Paused)),
+                Some(PlaybackStatus::Stopped) => events.push(active_event(EventKind::Stopped)),
+                None => {}
+            }
+        }
+        if let (Some(previous), Some(hidden)) = (self.hidden, next.hidden) {
+            if previous != hidden {
+                events.push(active_event(EventKind::HiddenToggled));
+            }
+        }
+        events
+    }
+
+    /// The environment variables passed to the hooks for `event`.
+    fn env(&self, event: &Event) -> Vec<(&'static str, String)> {
+        let mut env = vec![
+            ("MPRIS_EVENT", event.kind.name().to_string()),
+            ("MPRIS_PLAYER", event.player.clone().unwrap_or_default()),
+            (
+                "MPRIS_ACTIVE_PLAYER",
+                self.active().unwrap_or_default().to_string(),
+            ),
+            ("MPRIS_PLAYERS", self.players.join(" ")),
+            (
+                "MPRIS_HIDDEN",
+                self.hidden.map(|h| h.to_string()).unwrap_or_default(),
+            ),
+            (
+                "MPRIS_STATUS",
+                self.status().map(|s| format!("{s:?}")).unwrap_or_default(),
+            ),
+        ];
+        for (name, key) in [
+            ("MPRIS_TRACKID", "mpris:trackid"),
+            ("MPRIS_TITLE", "xesam:title"),
+            ("MPRIS_ARTIST", "xesam:artist"),
+            ("MPRIS_ALBUM", "xesam:album"),
+            ("MPRIS_URL", "xesam:url"),
+            ("MPRIS_LENGTH", "mpris:length"),
+        ] {
+            let value = self.player.as_ref().and_then(|info| info.metadata_text(key));
+            env.push((name, value.unwrap_or_default()));
+        }
+        env
+    }
+}
+
+/// Runs the hooks, keeping track of their last run for the rate limit.
+struct Runner {
+    hooks: Vec<(Hook, Option<Instant>)>,
+}
+
+impl Runner {
+    fn new(hooks: Vec<Hook>) -> Self {
+        Self {
+            hooks: hooks.into_iter().map(|hook| (hook, None)).collect(),
+        }
+    }
+
+    fn run(&mut self, event: &Event, env: &[(&'static str, String)]) {
+        for (hook, last_run) in &mut self.hooks {
+            if !hook.events.contains(&event.kind) {
+                continue;
+            }
+            let now = Instant::now();
+            if last_run.is_some_and(|last_run| now - last_run < hook.min_interval) {
+                info!(
+                    "skipping {:?} for {}, it ran less than {:?} ago",
+                    hook.command,
+                    event.kind.name(),
+                    hook.min_interval
+                );
+                continue;
+            }
+            *last_run = Some(now);
+            spawn(run_hook(hook.command.clone(), hook.timeout, env.to_vec()));
+        }
+    }
+}
+
+async fn run_hook(command: String, limit: Duration, env: Vec<(&'static str, String)>) {
+    info!("running {command:?}");
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(&command)
+        .envs(env)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            warn!("could not run {command:?}: {e}");
+            return;
+        }
+    };
+    match timeout(limit, child.wait()).await {
+        Ok(Ok(status)) if status.success() => {}
+        Ok(Ok(status)) => warn!("{command:?} failed: {status}"),
+        Ok(Err(e)) => warn!("waiting for {command:?}: {e}"),
+        // dropping the child kills it
+        Err(_) => warn!("{command:?} timed out after {limit:?}, killing it"),
+    }
+}
+
+pub fn main(options: Options) -> anyhow::Result<()> {
+    let level = if cfg!(debug_assertions) {
+        Level::DEBUG
+    } else {
+        Level::INFO
+    };
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::builder()
+                .with_default_directive(level.into())
+                .from_env_lossy(),
+        )
+        .with_writer(std::io::stderr)
+        .init();
+    let config = config::load(options.config.as_deref()).context("loading config")?;
+    if config.hooks.is_empty() {
+        warn!("no hooks configured");
+    }
+    let mut runner = Runner::new(config.hooks);
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("building tokio runtime")?
+        .block_on(async {
+            let conn = Connection::session()
+                .await
+                .context("connecting to session dbus")?;
+            let info = active_player_info(conn.clone())
+                .await
+                .context("following the active player")?;
+            let hidden = hidden(&conn).await.context("following the hide state")?;
+            let mut stream = pin!(combine_latest((info, hidden), Termination::Any));
+            let mut state = State::default();
+            while let Some((info, hidden)) = stream.next().await {
+                let next = state.update(info, hidden);
+                for event in state.events(&next) {
+                    runner.run(&event, &next.env(&event));
+                }
+                state = next;
+            }
+            Ok(())
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use mpris_dbus::proxies::PlaybackStatus;
+    use zbus::zvariant::Value;
+
+    use super::{Event, EventKind, Hook, State};
+    use crate::test_util::player_info;
+
+    fn state(players: &[&str], track: Option<(&str, PlaybackStatus)>) -> State {
+        State {
+            players: Arc::new(players.iter().map(|p| p.to_string()).collect()),
+            player: track.map(|(title, playback_status)| {
+                player_info(
+                    playback_status,
+                    &[
+                        ("xesam:title", Value::from(title)),
+                        ("xesam:artist", Value::from(vec!["A", "B"])),
+                    ],
+                )
+            }),
+            hidden: Some(false),
+        }
+    }
+
+    fn event(kind: EventKind, player: &str) -> Event {
+        Event {
+            kind,
+            player: Some(player.to_string()),
+        }
+    }
+
+    #[test]
+    fn events() {
+        let first = state(&["a"], Some(("x", PlaybackStatus::Playing)));
+        assert_eq!(
+            vec![
+                event(EventKind::PlayerAdded, "a"),
+                event(EventKind::TrackChanged, "a"),
+                event(EventKind::StartedPlaying, "a"),
+            ],
+            State::default().events(&first)
+        );
+        let paused = state(&["a"], Some(("x", PlaybackStatus::Paused)));
+        assert_eq!(vec![event(EventKind::Paused, "a")], first.events(&paused));
+        let next = state(&["b", "a"], Some(("y", PlaybackStatus::Paused)));
+        assert_eq!(
+            vec![
+                event(EventKind::PlayerAdded, "b"),
+                event(EventKind::TrackChanged, "b"),
+            ],
+            paused.events(&next)
+        );
+        let hidden = State {
+            hidden: Some(true),
+            ..next.clone()
+        };
+        assert_eq!(
+            vec![event(EventKind::HiddenToggled, "b")],
+            next.events(&hidden)
+        );
+        let empty = State {
+            hidden: Some(true),
+            ..state(&[], None)
+        };
+        assert_eq!(
+            vec![
+                event(EventKind::PlayerRemoved, "b"),
+                event(EventKind::PlayerRemoved, "a"),
+            ],
+            hidden.events(&empty)
+        );
+        let env = next.env(&event(EventKind::TrackChanged, "b"));
+        assert!(env.contains(&("MPRIS_ARTIST", "A, B".to_string())));
+        assert!(env.contains(&("MPRIS_PLAYERS", "b a".to_string())));
+    }
+
+    #[test]
+    fn errors_keep_state() {
+        let first = state(&["a"], Some(("x", PlaybackStatus::Playing)));
+        let next = first.update(
+            Some(Some(Err(mpris_dbus::Error::PlayerctldNotRunning))),
+            Some(Err(mpris_dbus::Error::HideServerMissing)),
+        );
+        assert!(first.events(&next).is_empty());
+    }
+
+    #[test]
+    fn config() {
+        let config: crate::config::Config = toml::from_str(
+            r#"
+            [[hook]]
+            events = ["track-changed", "player-added"]
+            command = "echo $MPRIS_TITLE"
+            min-interval = 0.5
+            "#,
+        )
+        .unwrap();
+        let hook: &Hook = &config.hooks[0];
+        assert_eq!(
+            vec![EventKind::TrackChanged, EventKind::PlayerAdded],
+            hook.events
+        );
+        assert_eq!(std::time::Duration::from_secs(30), hook.timeout);
+        assert_eq!(std::time::Duration::from_millis(500), hook.min_interval);
+        assert!(toml::from_str::<crate::config::Config>(
+            "[[hook]]\nevents = [\"played\"]\ncommand = \"true\""
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,384 @@
+use std::sync::{Arc, OnceLock};
+
+use mpris_dbus::player_info::PlayerInfo;
+use regex::Regex;
+use serde::{de::Error, Deserialize, Deserializer};
+use zbus::zvariant::Value;
+
+use super::{players::player_id, Info};
+
+/// Words of bracketed groups like "(Official Music Video)" or "[4K]" that are no part of the
+/// title.
+const NOISE: &str = r"official|officiel|music|lyrics?|video|audio|visuali[sz]er|clip|hd|hq|4k|8k|1080p|720p|mv|m/v|with";
+
+fn noise() -> &'static [Regex; 2] {
+    static NOISE_REGEXES: OnceLock<[Regex; 2]> = OnceLock::new();
+    NOISE_REGEXES.get_or_init(|| {
+        [
+            Regex::new(&format!(r"(?i)\s*[(\[](?:\s*(?:{NOISE})\b\.?)+\s*[)\]]")).unwrap(),
+            Regex::new(&format!(r"(?i)\s+[-|]\s+(?:(?:{NOISE})\b\s*)+$")).unwrap(),
+        ]
+    })
+}
+
+/// Separators between artist and title in titles of videos.
+const SEPARATORS: [&str; 3] = [" - ", " – ", " — "];
+
+/// Suffixes of the names of video channels instead of artists.
+const CHANNEL_SUFFIXES: [&str; 2] = ["VEVO", " - Topic"];
+
+/// Ids of the players the default rules apply to, see [`player_id`].
+const BROWSERS: [&str; 7] = [
+    "firefox",
+    "librewolf",
+    "chromium",
+    "chrome",
+    "brave",
+    "vivaldi",
+    "plasma-browser-integration",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Field {
+    Title,
+    Artist,
+}
+
+fn regex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Regex, D::Error> {
+    Regex::new(&String::deserialize(deserializer)?).map_err(D::Error::custom)
+}
+
+/// A step of the `[[cleanup]]` pipeline of the config file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "rule", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Rule {
+    /// Removes groups like "(Official Video)" or "[HD]" and suffixes like "| Official Audio"
+    /// from the title.
+    StripNoise,
+    /// Splits titles like "Artist - Title" if the artist is missing or a channel name ending
+    /// in "VEVO" or one of `channels`.
+    SplitArtist {
+        #[serde(default)]
+        channels: Vec<String>,
+    },
+    /// Removes "VEVO" and " - Topic" from the end of artists.
+    StripChannel,
+    /// Replaces all matches of `pattern` in the field.
+    Replace {
+        #[serde(default = "title")]
+        field: Field,
+        #[serde(deserialize_with = "regex")]
+        pattern: Regex,
+        #[serde(default)]
+        replacement: String,
+    },
+}
+
+fn title() -> Field {
+    Field::Title
+}
+
+/// The artist and title of a track while it is cleaned up.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Track {
+    pub artist: Option<String>,
+    pub title: Option<String>,
+}
+
+impl Rule {
+    fn apply(&self, track: &mut Track) {
+        match self {
+            Rule::StripNoise => {
+                if let Some(title) = &mut track.title {
+                    for regex in noise() {
+                        *title = regex.replace_all(title, "").trim().to_string();
+                    }
+                }
+            }
+            Rule::SplitArtist { channels } => {
+                let is_channel = match &track.artist {
+                    None => true,
+                    Some(artist) => {
+                        artist.is_empty()
+                            || artist.ends_with("VEVO")
+                            || channels.iter().any(|channel| channel == artist)
+                    }
+                };
+                let Some(title) = track.title.as_deref().filter(|_| is_channel) else {
+                    return;
+                };
+                let split = SEPARATORS
+                    .iter()
+                    .filter_map(|separator| title.split_once(separator))
+                    .min_by_key(|(artist, _)| artist.len());
+                if let Some((artist, title)) = split {
+                    let (artist, title) = (artist.trim(), title.trim());
+                    if !artist.is_empty() && !title.is_empty() {
+                        track.artist = Some(artist.to_string());
+                        track.title = Some(title.to_string());
+                    }
+                }
+            }
+            Rule::StripChannel => {
+                if let Some(artist) = &mut track.artist {
+                    for suffix in CHANNEL_SUFFIXES {
+                        if let Some(stripped) = artist.strip_suffix(suffix) {
+                            *artist = stripped.trim_end().to_string();
+                        }
+                    }
+                }
+            }
+            Rule::Replace {
+                field,
+                pattern,
+                replacement,
+            } => {
+                let value = match field {
+                    Field::Title => &mut track.title,
+                    Field::Artist => &mut track.artist,
+                };
+                if let Some(value) = value {
+                    *value = pattern
+                        .replace_all(value, replacement.as_str())
+                        .trim()
+                        .to_string();
+                }
+            }
+        }
+    }
+}
+
+/// The rules applied to the metadata before formatting.
+#[derive(Debug, Clone)]
+pub struct Cleanup {
+    rules: Arc<Vec<Rule>>,
+    /// Whether the rules only apply to [`BROWSERS`].
+    browsers_only: bool,
+}
+
+/// The default rules, applied to browsers only.
+impl Default for Cleanup {
+    fn default() -> Self {
+        Cleanup {
+            browsers_only: true,
+            ..Cleanup::new(vec![
+                Rule::StripNoise,
+                Rule::SplitArtist {
+                    channels: Vec::new(),
+                },
+                Rule::StripChannel,
+            ])
+        }
+    }
+}
+
+impl Cleanup {
+    /// Applies `rules` to the tracks of every player.
+    pub fn new(rules: Vec<Rule>) -> Self {
+        Cleanup {
+            rules: Arc::new(rules),
+            browsers_only: false,
+        }
+    }
+
+    pub fn track(&self, mut track: Track) -> Track {
+        for rule in self.rules.iter() {
+            rule.apply(&mut track);
+        }
+        track
+    }
+
+    /// Replaces the artist and title in the metadata of `info` with the cleaned up ones.
+    pub fn info(&self, info: Info) -> Info {
+        let Some(Some(Ok((players, info)))) = info else {
+            return info;
+        };
+        let browser = players
+            .first()
+            .is_some_and(|player| BROWSERS.contains(&player_id(player)));
+        if self.browsers_only && !browser {
+            return Some(Some(Ok((players, info))));
+        }
+        Some(Some(Ok((players, self.player_info(info)))))
+    }
+
+    fn player_info(&self, mut info: PlayerInfo) -> PlayerInfo {
+        let track = Track {
+            artist: info.metadata_text("xesam:artist"),
+            title: info.metadata_text("xesam:title"),
+        };
+        let cleaned = self.track(track.clone());
+        if cleaned == track {
+            return info;
+        }
+        let metadata = Arc::make_mut(&mut info.metadata);
+        for (key, value) in [
+            ("xesam:artist", cleaned.artist),
+            ("xesam:title", cleaned.title),
+        ] {
+            match value.filter(|value| !value.is_empty()) {
+                Some(value) => metadata.insert(key.to_string(), Value::from(value).into()),
+                None => metadata.remove(key),
+            };
+        }
+        info
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use mpris_dbus::proxies::PlaybackStatus;
+    use zbus::zvariant::Value;
+
+    use super::{Cleanup, Info, Rule, Track};
+    use crate::test_util::player_info;
+
+    fn clean(cleanup: &Cleanup, artist: Option<&str>, title: &str) -> (Option<String>, String) {
+        let track = cleanup.track(Track {
+            artist: artist.map(str::to_string),
+            title: Some(title.to_string()),
+        });
+        (track.artist, track.title.unwrap_or_default())
+    }
+
+    #[test]
+    fn real_world_titles() {
+        let cleanup = Cleanup::default();
+        let cases = [
+            (
+                Some("RickAstleyVEVO"),
+                "Rick Astley - Never Gonna Give You Up (Official Music Video) [4K]",
+                Some("Rick Astley"),
+                "Never Gonna Give You Up",
+            ),
+            (
+                Some("Daft Punk - Topic"),
+                "One More Time",
+                Some("Daft Punk"),
+                "One More Time",
+            ),
+            (
+                None,
+                "Queen – Bohemian Rhapsody (Official Video Remastered)",
+                Some("Queen"),
+                "Bohemian Rhapsody (Official Video Remastered)",
+            ),
+            (
+                Some(""),
+                "a-ha - Take On Me (Official Video) [Remastered in 4K]",
+                Some("a-ha"),
+                "Take On Me [Remastered in 4K]",
+            ),
+            (
+                Some("BLACKPINK"),
+                "BLACKPINK - '뚜두뚜두 (DDU-DU DDU-DU)' M/V",
+                Some("BLACKPINK"),
+                "BLACKPINK - '뚜두뚜두 (DDU-DU DDU-DU)' M/V",
+            ),
+            (
+                Some("YOASOBI"),
+                "YOASOBI「アイドル」 Official Music Video",
+                Some("YOASOBI"),
+                "YOASOBI「アイドル」 Official Music Video",
+            ),
+            (
+                None,
+                "Nirvana - Smells Like Teen Spirit (Official Music Video) | HD",
+                Some("Nirvana"),
+                "Smells Like Teen Spirit",
+            ),
+            (
+                Some("Stromae"),
+                "Stromae - Papaoutai (Clip Officiel)",
+                Some("Stromae"),
+                "Stromae - Papaoutai",
+            ),
+            (
+                Some("Pink Floyd"),
+                "Wish You Were Here - 2011 Remaster (Lyrics)",
+                Some("Pink Floyd"),
+                "Wish You Were Here - 2011 Remaster",
+            ),
+            (
+                Some("Miles Davis"),
+                "So What (feat. John Coltrane) [Audio]",
+                Some("Miles Davis"),
+                "So What (feat. John Coltrane)",
+            ),
+        ];
+        for (artist, title, expected_artist, expected_title) in cases {
+            assert_eq!(
+                (expected_artist.map(str::to_string), expected_title.to_string()),
+                clean(&cleanup, artist, title),
+                "{artist:?} {title:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn config() {
+        let config: crate::config::Config = toml::from_str(
+            r#"
+            [[cleanup]]
+            rule = "split-artist"
+            channels = ["Some Label"]
+            [[cleanup]]
+            rule = "replace"
+            pattern = '\s*\(feat\. [^)]*\)'
+            [[cleanup]]
+            rule = "replace"
+            field = "artist"
+            pattern = '^The '
+            "#,
+        )
+        .unwrap();
+        let cleanup = Cleanup::new(config.cleanup.unwrap());
+        assert_eq!(
+            (Some("Beatles".to_string()), "Let It Be".to_string()),
+            clean(&cleanup, Some("Some Label"), "The Beatles - Let It Be (feat. X)")
+        );
+        // without the noise rule groups stay
+        assert_eq!(
+            (Some("Beatles".to_string()), "Help! (Official Video)".to_string()),
+            clean(&cleanup, Some("The Beatles"), "Help! (Official Video)")
+        );
+        assert!(toml::from_str::<crate::config::Config>("[[cleanup]]\nrule = \"split\"").is_err());
+    }
+
+    #[test]
+    fn browsers_only() {
+        let info = |player: &str| {
+            let info = player_info(
+                PlaybackStatus::Playing,
+                &[("xesam:title", Value::from("Artist - Title (Official Video)"))],
+            );
+            Some(Some(Ok((Arc::new(vec![player.to_string()]), info))))
+        };
+        let title = |info: Info| {
+            let Some(Some(Ok((_, info)))) = info else {
+                panic!("no player info");
+            };
+            info.metadata_text("xesam:title")
+        };
+        let browser = "org.mpris.MediaPlayer2.firefox.instance_1_23";
+        let player = "org.mpris.MediaPlayer2.mpv";
+        let cleanup = Cleanup::default();
+        assert_eq!(
+            Some("Title".to_string()),
+            title(cleanup.info(info(browser)))
+        );
+        assert_eq!(
+            Some("Artist - Title (Official Video)".to_string()),
+            title(cleanup.info(info(player)))
+        );
+        // configured rules apply to every player
+        let cleanup = Cleanup::new(vec![Rule::StripNoise]);
+        assert_eq!(
+            Some("Artist - Title".to_string()),
+            title(cleanup.info(info(player)))
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use std::{borrow::Cow, future::ready, time::Duration};
+
+use mpris_dbus::{proxies::PlaybackStatus, stream::StreamExt2};
+use tokio::time::{interval_at, Instant};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+use zbus::export::futures_util::{
+    stream::{self, empty, once},
+    Stream, StreamExt,
+};
+
+use super::{track_name, Info};
+
+/// Put between the end and the start of a scrolling name.
+const GAP: &str = "   ";
+
+/// Scrolling of names wider than `width` columns by one grapheme every `interval`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scroll {
+    pub width: usize,
+    pub interval: Duration,
+}
+
+/// The `width` columns of `text` starting `offset` graphemes into it, wrapping around. Text
+/// that fits is returned as is.
+pub fn window(text: &str, width: usize, offset: usize) -> Cow<'_, str> {
+    if text.width() <= width {
+        return Cow::Borrowed(text);
+    }
+    let graphemes: Vec<&str> = text.graphemes(true).chain(GAP.graphemes(true)).collect();
+    let mut window = String::new();
+    let mut used = 0;
+    for grapheme in graphemes.iter().cycle().skip(offset % graphemes.len()) {
+        let grapheme_width = grapheme.width();
+        if used + grapheme_width > width {
+            break;
+        }
+        window.push_str(grapheme);
+        used += grapheme_width;
+    }
+    // a wide grapheme did not fit into the last column
+    window.push_str(&" ".repeat(width - used));
+    Cow::Owned(window)
+}
+
+/// Wether the name of `info` has to scroll.
+fn scrolls(info: &Info, width: usize) -> bool {
+    match info {
+        Some(Some(Ok((_, info)))) => {
+            info.playback_status == PlaybackStatus::Playing && track_name(info).width() > width
+        }
+        _ => false,
+    }
+}
+
+fn ticks(period: Duration) -> impl Stream<Item = ()> {
+    stream::unfold(
+        interval_at(Instant::now() + period, period),
+        |mut interval| async move {
+            interval.tick().await;
+            Some(((), interval))
+        },
+    )
+}
+
+enum Event {
+    Info(Info),
+    Tick,
+}
+
+/// Yields every info of `stream` with the offset of its scrolling name. While the name of a
+/// playing player is too wide, the info is repeated with the next offset on every tick. The
+/// offset starts over when the name changes and stays while the player is paused.
+pub fn scrolling<S>(stream: S, scroll: Option<Scroll>) -> impl Stream<Item = (Info, usize)>
+where
+    S: Stream<Item = Info>,
+{
+    stream
+        .map(move |info| {
+            let ticks = match scroll {
+                Some(scroll) if scrolls(&info, scroll.width) => {
+                    ticks(scroll.interval).map(|()| Event::Tick).left_stream()
+                }
+                _ => empty().right_stream(),
+            };
+            once(ready(Event::Info(info))).chain(ticks)
+        })
+        .flatten_newest()
+        .fold_map(
+            (None, None, 0),
+            |event, (last, name, offset): &mut (Info, Option<String>, usize)| {
+                match event {
+                    Event::Info(info) => {
+                        let next = match &info {
+                            Some(Some(Ok((_, info)))) => Some(track_name(info).into_owned()),
+                            _ => None,
+                        };
+                        if next != *name {
+                            *name = next;
+                            *offset = 0;
+                        }
+                        *last = info;
+                    }
+                    Event::Tick => *offset += 1,
+                }
+                (last.clone(), *offset)
+            },
+        )
+}
+
+#[cfg(test)]
+mod test {
+    use std::{future::ready, pin::pin, sync::Arc, time::Duration};
+
+    use mpris_dbus::proxies::PlaybackStatus;
+    use tokio::time::{sleep, timeout};
+    use zbus::{
+        export::futures_util::{
+            stream::{once, pending},
+            StreamExt,
+        },
+        zvariant::Value,
+    };
+
+    use super::{scrolling, window, Info, Scroll};
+    use crate::test_util::player_info;
+
+    fn info(playback_status: PlaybackStatus) -> Info {
+        Some(Some(Ok((
+            Arc::new(vec!["org.mpris.MediaPlayer2.test".to_string()]),
+            player_info(playback_status, &[("xesam:title", Value::from("title"))]),
+        ))))
+    }
+
+    #[test]
+    fn windows() {
+        assert_eq!("short", window("short", 5, 3));
+        assert_eq!("abc", window("abcdef", 3, 0));
+        assert_eq!("def", window("abcdef", 3, 3));
+        assert_eq!("f  ", window("abcdef", 3, 5));
+        assert_eq!(" ab", window("abcdef", 3, 8));
+        assert_eq!("abc", window("abcdef", 3, 9));
+        // wide characters take two columns, combined graphemes stay together
+        assert_eq!("日本", window("日本語です", 4, 0));
+        assert_eq!("本語 ", window("日本語です", 5, 1));
+        assert_eq!("e\u{301}🇩🇪", window("e\u{301}🇩🇪xyz", 3, 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pauses() {
+        let infos = once(ready(info(PlaybackStatus::Playing)))
+            .chain(once(async {
+                sleep(Duration::from_millis(1100)).await;
+                info(PlaybackStatus::Paused)
+            }))
+            .chain(pending());
+        let scroll = Scroll {
+            width: 3,
+            interval: Duration::from_millis(500),
+        };
+        let mut stream = pin!(scrolling(infos, Some(scroll)));
+        let mut offsets = Vec::new();
+        while let Ok(Some((_, offset))) = timeout(Duration::from_secs(5), stream.next()).await {
+            offsets.push(offset);
+        }
+        // the paused info keeps the offset and stops the ticks
+        assert_eq!(vec![0, 1, 2, 2], offsets);
+    }
+}
@@ -1,25 +1,34 @@
+use std::{borrow::Cow, path::Path, sync::Arc};
 #[cfg(feature = "info_polybar_yambar")]
-use std::{pin::pin, sync::Arc, fs::{File, create_dir_all}, path::PathBuf, env, process};
+use std::{pin::pin, fs::{File, create_dir_all}, path::PathBuf, env, process};
 
 use anyhow::Context;
 
 #[cfg(feature = "info_polybar_yambar")]
 use anyhow::anyhow;
 use clap::Subcommand;
-#[cfg(feature = "info_polybar_yambar")]
-use mpris_dbus::player_info::PlayerInfo;
+use cleanup::Cleanup;
+use marquee::Scroll;
+use players::Players;
+use truncate::Truncate;
+use mpris_dbus::{hide::AutoHide, player_info::PlayerInfo};
 #[cfg(feature = "info_polybar_yambar")]
 use zbus::{export::futures_util::StreamExt, Connection};
 
 #[cfg(feature = "info_polybar_yambar")]
 use time::macros::format_description;
-use tracing::Level;
+use tracing::{warn, Level};
 #[cfg(feature = "info_polybar_yambar")]
 use tracing_subscriber::fmt::time::LocalTime;
 use tracing_subscriber::EnvFilter;
+use unicode_width::UnicodeWidthStr;
 #[cfg(feature = "info_waybar")]
 use tracing_subscriber::{layer::SubscriberExt, registry, util::SubscriberInitExt};
 
+pub mod cleanup;
+pub mod marquee;
+pub mod players;
+pub mod truncate;
 #[cfg(feature = "info_polybar_yambar")]
 mod polybar;
 #[cfg(feature = "info_waybar")]
@@ -41,7 +50,14 @@ pub enum Bars {
     Waybar,
 }
 
-pub fn main(bar: Bars, hidden: bool) ->  anyhow::Result<()> {
+pub fn main(
+    bar: Bars,
+    hidden: bool,
+    auto_hide: AutoHide,
+    scroll: Option<Scroll>,
+    truncate: Option<Truncate>,
+    config: Option<&Path>,
+) -> anyhow::Result<()> {
     let level = if cfg!(debug_assertions) {
         Level::DEBUG
     } else {
@@ -58,11 +74,12 @@ pub fn main(bar: Bars, hidden: bool) ->  anyhow::Result<()> {
                         .from_env_lossy(),
                 )
                 .init();
+            let (cleanup, players) = settings(config);
             let rt = tokio::runtime::Builder::new_current_thread()
                 .enable_all()
                 .build()
                 .context("building tokio runtime")?;
-            rt.block_on(waybar::waybar(hidden))
+            rt.block_on(waybar::waybar(hidden, auto_hide, scroll, truncate, cleanup, players))
         }
         #[cfg(feature = "info_polybar_yambar")]
         command @ Bars::Yambar
@@ -90,26 +107,37 @@ pub fn main(bar: Bars, hidden: bool) ->  anyhow::Result<()> {
                 ))
                 .with_writer(logfile)
                 .init();
+            let (cleanup, players) = settings(config);
             let rt = tokio::runtime::Builder::new_current_thread()
                 .enable_all()
                 .build()
                 .context("building tokio runtime")?;
             rt.block_on(async move {
-                let mut stream = pin!(
-                    mpris_dbus::hide::hidden_active_player_info(
-                        &Connection::session().await?,
-                        hidden
-                    )
-                    .await?
-                );
-                while let Some(info) = stream.next().await {
+                let mut players = players;
+                let conn = Connection::session().await?;
+                let mut stream = pin!(marquee::scrolling(
+                    mpris_dbus::hide::hidden_active_player_info_with(&conn, hidden, auto_hide)
+                        .await?
+                        .map(|info| cleanup.info(info)),
+                    scroll
+                ));
+                let mut last = None;
+                while let Some((info, offset)) = players.next(&mut stream, &mut last).await {
+                    players.load(&conn, &info);
+                    let display = Display {
+                        scroll,
+                        offset,
+                        truncate,
+                    };
                     match &command {
                         Bars::Polybar {
                             icon_font,
                             hide_cmd,
                             name_len,
-                        } => polybar::polybar(info, *icon_font, hide_cmd, *name_len),
-                        Bars::Yambar => yambar::yambar(info),
+                        } => polybar::polybar(
+                            info, &players, *icon_font, hide_cmd, *name_len, display,
+                        ),
+                        Bars::Yambar => yambar::yambar(info, &players, display),
                         #[cfg(feature = "info_waybar")]
                         Bars::Waybar => unreachable!(),
                     }
@@ -120,5 +148,61 @@ pub fn main(bar: Bars, hidden: bool) ->  anyhow::Result<()> {
     }
 }
 
-#[cfg(feature = "info_polybar_yambar")]
-type Info = Option<Option<Result<(Arc<Vec<String>>, PlayerInfo), Arc<zbus::Error>>>>;
+/// The cleanup and players of the config file. A config that cannot be loaded is logged and
+/// replaced by the defaults, so a typo does not take down the bar.
+fn settings(path: Option<&Path>) -> (Cleanup, Players) {
+    let config = crate::config::load(path).unwrap_or_else(|e| {
+        warn!("{:#}, using the default config", e.context("loading config"));
+        Default::default()
+    });
+    let cleanup = match config.cleanup {
+        Some(rules) => Cleanup::new(rules),
+        None => Cleanup::default(),
+    };
+    (
+        cleanup,
+        Players::new(config.players, players::applications_dirs()),
+    )
+}
+
+type Info = Option<Option<mpris_dbus::Result<(Arc<Vec<String>>, PlayerInfo)>>>;
+
+/// The name shown for the track, `<artist> - <title>` unless the title starts with the artist.
+pub fn track_name(info: &PlayerInfo) -> Cow<'_, str> {
+    let mut artist = info.metadata_text("xesam:artist");
+    let title = info.metadata_text("xesam:title");
+    if let (Some(name), Some(title)) = (&artist, &title) {
+        if title.starts_with(name.as_str()) {
+            artist = None;
+        }
+    }
+    match (artist, title) {
+        (Some(name), Some(title)) => Cow::from(format!("{name} - {title}")),
+        (Some(name), None) => Cow::from(name),
+        (None, Some(title)) => Cow::from(title),
+        (None, None) => Cow::from(""),
+    }
+}
+
+/// How the track name is displayed.
+#[derive(Debug, Clone, Copy)]
+pub struct Display {
+    pub scroll: Option<Scroll>,
+    /// Offset of the scrolling name.
+    pub offset: usize,
+    /// Truncation of names that do not scroll.
+    pub truncate: Option<Truncate>,
+}
+
+impl Display {
+    /// The visible part of `name`.
+    pub fn name<'a>(&self, name: Cow<'a, str>) -> Cow<'a, str> {
+        match (self.scroll, self.truncate) {
+            (Some(scroll), _) if name.width() > scroll.width => {
+                Cow::Owned(marquee::window(&name, scroll.width, self.offset).into_owned())
+            }
+            (None, Some(truncate)) => truncate.apply(name),
+            _ => name,
+        }
+    }
+}
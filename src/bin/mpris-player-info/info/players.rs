@@ -0,0 +1,475 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::PathBuf,
+    pin::pin,
+    sync::Arc,
+    time::Duration,
+};
+
+use mpris_dbus::proxies::MediaPlayer2Proxy;
+use serde::Deserialize;
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    task::{spawn, spawn_blocking},
+    time::timeout,
+};
+use tracing::{debug, warn};
+use zbus::{
+    export::futures_util::{
+        future::{select, Either},
+        Stream, StreamExt,
+    },
+    CacheProperties, Connection,
+};
+
+use super::Info;
+use crate::dirs;
+
+const MPRIS_PREFIX: &str = "org.mpris.MediaPlayer2.";
+
+/// Glyph of players without one of their own, the Nerd Font music note.
+pub const DEFAULT_GLYPH: &str = "\u{f001}";
+
+/// How long a player may take to report its desktop entry.
+const DESKTOP_ENTRY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Names and Nerd Font glyphs of common players by their id.
+const BUILTIN: [(&str, &str, &str); 18] = [
+    ("spotify", "Spotify", "\u{f1bc}"),
+    ("spotifyd", "Spotify", "\u{f1bc}"),
+    ("ncspot", "ncspot", "\u{f1bc}"),
+    ("firefox", "Firefox", "\u{f269}"),
+    ("librewolf", "LibreWolf", "\u{f269}"),
+    ("chromium", "Chromium", "\u{f268}"),
+    ("brave", "Brave", "\u{f268}"),
+    ("plasma-browser-integration", "Browser", "\u{f0ac}"),
+    ("mpv", "mpv", "\u{f03d}"),
+    ("vlc", "VLC", "\u{f057c}"),
+    ("mpd", "MPD", DEFAULT_GLYPH),
+    ("cmus", "cmus", DEFAULT_GLYPH),
+    ("rhythmbox", "Rhythmbox", DEFAULT_GLYPH),
+    ("audacious", "Audacious", DEFAULT_GLYPH),
+    ("strawberry", "Strawberry", DEFAULT_GLYPH),
+    ("elisa", "Elisa", DEFAULT_GLYPH),
+    ("lollypop", "Lollypop", DEFAULT_GLYPH),
+    ("kdeconnect", "KDE Connect", "\u{f10b}"),
+];
+
+/// A `[player.<id>]` table of the config file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Override {
+    pub name: Option<String>,
+    pub glyph: Option<String>,
+    pub icon: Option<String>,
+}
+
+/// How a player is shown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Player {
+    /// The `DesktopEntry` of the player, or its bus name without `org.mpris.MediaPlayer2.`
+    /// and the instance suffix if it has none.
+    pub id: String,
+    pub name: String,
+    /// Text shown as the icon of the player in the bars.
+    pub glyph: String,
+    /// Name of the icon in the icon theme, from the desktop entry.
+    pub icon: Option<String>,
+}
+
+/// A player without a bus name.
+impl Default for Player {
+    fn default() -> Self {
+        Player {
+            id: String::new(),
+            name: String::new(),
+            glyph: DEFAULT_GLYPH.to_string(),
+            icon: None,
+        }
+    }
+}
+
+/// The id of the player owning `bus_name`.
+pub fn player_id(bus_name: &str) -> &str {
+    let id = bus_name.strip_prefix(MPRIS_PREFIX).unwrap_or(bus_name);
+    match id.find(".instance") {
+        Some(index) => &id[..index],
+        None => id,
+    }
+}
+
+/// `$XDG_DATA_HOME/applications` followed by `applications` in each of `$XDG_DATA_DIRS`.
+pub fn applications_dirs() -> Vec<PathBuf> {
+    dirs::data_dirs()
+        .into_iter()
+        .map(|dir| dir.join("applications"))
+        .collect()
+}
+
+/// `Name` and `Icon` of the `[Desktop Entry]` group of a desktop file.
+fn parse_desktop(content: &str) -> (Option<String>, Option<String>) {
+    let (mut name, mut icon) = (None, None);
+    let mut in_entry = false;
+    for line in content.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_entry = line == "[Desktop Entry]";
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=').filter(|_| in_entry) else {
+            continue;
+        };
+        let value = Some(value.trim().to_string()).filter(|value| !value.is_empty());
+        match key.trim() {
+            "Name" => name = value,
+            "Icon" => icon = value,
+            _ => {}
+        }
+    }
+    (name, icon)
+}
+
+/// The desktop file of `id`, `<id>.desktop` or a reverse DNS name ending in the id like
+/// `org.videolan.VLC.desktop` for `vlc`.
+fn desktop_file(id: &str, dirs: &[PathBuf]) -> Option<PathBuf> {
+    let matches = |stem: &str| {
+        stem.eq_ignore_ascii_case(id)
+            || stem
+                .rsplit_once('.')
+                .is_some_and(|(_, last)| last.eq_ignore_ascii_case(id))
+    };
+    dirs.iter().find_map(|dir| {
+        let exact = dir.join(format!("{id}.desktop"));
+        if exact.is_file() {
+            return Some(exact);
+        }
+        let mut files: Vec<PathBuf> = fs::read_dir(dir)
+            .ok()?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "desktop")
+                    && path
+                        .file_stem()
+                        .and_then(|stem| stem.to_str())
+                        .is_some_and(matches)
+            })
+            .collect();
+        files.sort();
+        files.into_iter().next()
+    })
+}
+
+/// The `DesktopEntry` of the player owning `bus_name`, `None` if it has none or does not
+/// answer in time.
+async fn desktop_entry(conn: &Connection, bus_name: &str) -> Option<String> {
+    let read = async {
+        MediaPlayer2Proxy::builder(conn)
+            .destination(bus_name)?
+            .cache_properties(CacheProperties::No)
+            .build()
+            .await?
+            .desktop_entry()
+            .await
+    };
+    match timeout(DESKTOP_ENTRY_TIMEOUT, read).await {
+        Ok(Ok(entry)) => Some(entry).filter(|entry| !entry.is_empty()),
+        Ok(Err(e)) => {
+            debug!("no desktop entry for {bus_name}: {e}");
+            None
+        }
+        Err(_) => {
+            debug!("{bus_name} did not report its desktop entry in time");
+            None
+        }
+    }
+}
+
+/// Names, glyphs and icons of players by their ids.
+#[derive(Debug)]
+struct Resolver {
+    overrides: HashMap<String, Override>,
+    dirs: Vec<PathBuf>,
+}
+
+impl Resolver {
+    /// The player owning `bus_name` with the desktop entry `entry`, reads its desktop file.
+    fn player(&self, bus_name: &str, entry: Option<&str>) -> Player {
+        self.resolve(entry, player_id(bus_name), &self.dirs)
+    }
+
+    /// The player owning `bus_name` from the overrides and built in names only.
+    fn fallback(&self, bus_name: &str) -> Player {
+        self.resolve(None, player_id(bus_name), &[])
+    }
+
+    fn resolve(&self, entry: Option<&str>, id: &str, dirs: &[PathBuf]) -> Player {
+        let ids: Vec<&str> = entry.into_iter().chain([id]).collect();
+        let (desktop_name, desktop_icon) = ids
+            .iter()
+            .find_map(|id| desktop_file(id, dirs))
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|content| parse_desktop(&content))
+            .unwrap_or_default();
+        let builtin = ids.iter().find_map(|id| {
+            BUILTIN
+                .iter()
+                .find(|(builtin, _, _)| builtin.eq_ignore_ascii_case(id))
+        });
+        let user = ids
+            .iter()
+            .find_map(|id| self.overrides.get(*id))
+            .cloned()
+            .unwrap_or_default();
+        let id = ids[0];
+        Player {
+            id: id.to_string(),
+            name: user
+                .name
+                .or(desktop_name)
+                .or_else(|| builtin.map(|(_, name, _)| name.to_string()))
+                .unwrap_or_else(|| id.to_string()),
+            glyph: user
+                .glyph
+                .or_else(|| builtin.map(|(_, _, glyph)| glyph.to_string()))
+                .unwrap_or_else(|| DEFAULT_GLYPH.to_string()),
+            icon: user.icon.or(desktop_icon),
+        }
+    }
+}
+
+/// Resolves and caches the [`Player`] of each bus name in the background.
+#[derive(Debug)]
+pub struct Players {
+    resolver: Arc<Resolver>,
+    /// Resolved players of the bus names in the last player list.
+    cache: HashMap<String, Player>,
+    /// Bus names of the last player list that are being resolved.
+    pending: HashSet<String>,
+    sender: UnboundedSender<(String, Player)>,
+    receiver: UnboundedReceiver<(String, Player)>,
+}
+
+impl Players {
+    /// `overrides` are keyed by player id, desktop files are searched in `dirs` in order.
+    pub fn new(overrides: HashMap<String, Override>, dirs: Vec<PathBuf>) -> Self {
+        let (sender, receiver) = unbounded_channel();
+        Players {
+            resolver: Arc::new(Resolver { overrides, dirs }),
+            cache: HashMap::new(),
+            pending: HashSet::new(),
+            sender,
+            receiver,
+        }
+    }
+
+    /// Forgets the players that are no longer in the player list of `info` and starts
+    /// resolving its active player from its `DesktopEntry` the first time it is seen, see
+    /// [`get`](Self::get) and [`next`](Self::next).
+    pub fn load(&mut self, conn: &Connection, info: &Info) {
+        let Some(Some(Ok((names, _)))) = info else {
+            return;
+        };
+        // browsers get a new bus name for each instance
+        self.cache.retain(|bus_name, _| names.contains(bus_name));
+        self.pending.retain(|bus_name| names.contains(bus_name));
+        let Some(bus_name) = names.first() else {
+            return;
+        };
+        if self.cache.contains_key(bus_name) || !self.pending.insert(bus_name.clone()) {
+            return;
+        }
+        let (conn, resolver, sender) = (conn.clone(), self.resolver.clone(), self.sender.clone());
+        let bus_name = bus_name.clone();
+        spawn(async move {
+            let entry = desktop_entry(&conn, &bus_name).await;
+            let player = spawn_blocking({
+                let bus_name = bus_name.clone();
+                move || resolver.player(&bus_name, entry.as_deref())
+            })
+            .await;
+            match player {
+                // the receiver lives as long as the players
+                Ok(player) => _ = sender.send((bus_name, player)),
+                Err(e) => warn!("resolving player {bus_name} failed: {e}"),
+            }
+        });
+    }
+
+    /// The player owning `bus_name`. Until it is [loaded](Self::load) it only comes from the
+    /// overrides and built in names of the id of the bus name. Overrides come first, then the
+    /// desktop file and the built in names, glyphs only come from overrides and built ins. The
+    /// desktop entry is looked up before the id of the bus name for each of them.
+    pub fn get(&self, bus_name: &str) -> Player {
+        match self.cache.get(bus_name) {
+            Some(player) => player.clone(),
+            None => self.resolver.fallback(bus_name),
+        }
+    }
+
+    /// Waits until a player of the last player list is resolved.
+    async fn resolved(&mut self) {
+        while let Some((bus_name, player)) = self.receiver.recv().await {
+            if self.pending.remove(&bus_name) {
+                debug!("resolved player {bus_name}: {player:?}");
+                self.cache.insert(bus_name, player);
+                return;
+            }
+        }
+    }
+
+    /// The next item of `items`, or the `last` one again once a player is resolved so it is
+    /// shown with its name and glyph.
+    pub async fn next<S>(&mut self, items: &mut S, last: &mut Option<S::Item>) -> Option<S::Item>
+    where
+        S: Stream + Unpin,
+        S::Item: Clone,
+    {
+        loop {
+            match select(items.next(), pin!(self.resolved())).await {
+                Either::Left((item, _)) => {
+                    last.clone_from(&item);
+                    return item;
+                }
+                Either::Right(_) if last.is_some() => return last.clone(),
+                Either::Right(_) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, env, fs, process};
+
+    use super::{parse_desktop, player_id, Player, Players, DEFAULT_GLYPH};
+
+    #[test]
+    fn ids() {
+        assert_eq!("spotify", player_id("org.mpris.MediaPlayer2.spotify"));
+        assert_eq!("vlc", player_id("org.mpris.MediaPlayer2.vlc.instance7389"));
+        assert_eq!(
+            "firefox",
+            player_id("org.mpris.MediaPlayer2.firefox.instance_1_84")
+        );
+        assert_eq!("mpv", player_id("mpv"));
+    }
+
+    #[test]
+    fn desktop() {
+        assert_eq!(
+            (
+                Some("VLC media player".to_string()),
+                Some("vlc".to_string())
+            ),
+            parse_desktop(
+                "# comment\n[Desktop Entry]\nName=VLC media player\nName[de]=VLC-Player\n\
+                 Icon=vlc\n\n[Desktop Action play]\nName=Play\nIcon=play\n"
+            )
+        );
+        assert_eq!((None, None), parse_desktop("[Other]\nName=x\n"));
+    }
+
+    #[test]
+    fn resolve() {
+        let dir = env::temp_dir().join(format!("mpris-player-info-players-{}", process::id()));
+        let (local, system) = (dir.join("local"), dir.join("system"));
+        fs::create_dir_all(&local).unwrap();
+        fs::create_dir_all(&system).unwrap();
+        fs::write(
+            system.join("org.videolan.VLC.desktop"),
+            "[Desktop Entry]\nName=VLC media player\nIcon=vlc\n",
+        )
+        .unwrap();
+        fs::write(
+            system.join("spotify.desktop"),
+            "[Desktop Entry]\nName=Spotify (system)\nIcon=spotify-client\n",
+        )
+        .unwrap();
+        fs::write(
+            local.join("spotify.desktop"),
+            "[Desktop Entry]\nName=Spotify (local)\nIcon=spotify-local\n",
+        )
+        .unwrap();
+        let overrides = toml::from_str(
+            r#"
+            [mpv]
+            glyph = "M"
+            [spotify]
+            name = "Music"
+            "#,
+        )
+        .unwrap();
+        let players = Players::new(overrides, vec![local.clone(), system.clone()]);
+        assert_eq!(
+            Player {
+                id: "vlc".to_string(),
+                name: "VLC media player".to_string(),
+                glyph: "\u{f057c}".to_string(),
+                icon: Some("vlc".to_string()),
+            },
+            players
+                .resolver
+                .player("org.mpris.MediaPlayer2.vlc.instance42", None)
+        );
+        assert_eq!(
+            Player {
+                id: "spotify".to_string(),
+                name: "Music".to_string(),
+                glyph: "\u{f1bc}".to_string(),
+                icon: Some("spotify-local".to_string()),
+            },
+            players
+                .resolver
+                .player("org.mpris.MediaPlayer2.spotify", None)
+        );
+        assert_eq!(
+            Player {
+                id: "mpv".to_string(),
+                name: "mpv".to_string(),
+                glyph: "M".to_string(),
+                icon: None,
+            },
+            players.resolver.player("org.mpris.MediaPlayer2.mpv", None)
+        );
+        // desktop files are only read in the background
+        assert_eq!(
+            Player {
+                id: "vlc".to_string(),
+                name: "VLC".to_string(),
+                glyph: "\u{f057c}".to_string(),
+                icon: None,
+            },
+            players.get("org.mpris.MediaPlayer2.vlc.instance42")
+        );
+        assert_eq!(
+            Player {
+                id: "unknown".to_string(),
+                name: "unknown".to_string(),
+                glyph: DEFAULT_GLYPH.to_string(),
+                icon: None,
+            },
+            Players::new(HashMap::new(), vec![system.clone()])
+                .resolver
+                .player("org.mpris.MediaPlayer2.unknown", None)
+        );
+        // the desktop entry comes before the bus name
+        fs::write(
+            system.join("firefox.desktop"),
+            "[Desktop Entry]\nName=Firefox\nIcon=firefox\n",
+        )
+        .unwrap();
+        let players = Players::new(HashMap::new(), vec![system]);
+        let bus_name = "org.mpris.MediaPlayer2.plasma-browser-integration";
+        assert_eq!(
+            Player {
+                id: "firefox".to_string(),
+                name: "Firefox".to_string(),
+                glyph: "\u{f269}".to_string(),
+                icon: Some("firefox".to_string()),
+            },
+            players.resolver.player(bus_name, Some("firefox"))
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
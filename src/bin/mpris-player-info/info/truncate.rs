@@ -0,0 +1,132 @@
+use std::borrow::Cow;
+
+use clap::ValueEnum;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+const ELLIPSIS: &str = "…";
+
+/// Where the ellipsis replaces the text that does not fit.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Ellipsis {
+    #[default]
+    End,
+    Middle,
+}
+
+/// Truncation of names wider than `width` columns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Truncate {
+    pub width: usize,
+    pub ellipsis: Ellipsis,
+}
+
+impl Truncate {
+    pub fn apply<'a>(&self, text: Cow<'a, str>) -> Cow<'a, str> {
+        match truncate(&text, self.width, self.ellipsis) {
+            // only text that fits is borrowed
+            Cow::Borrowed(_) => text,
+            Cow::Owned(truncated) => Cow::Owned(truncated),
+        }
+    }
+}
+
+/// The longest start of `text` that is at most `width` columns wide.
+fn prefix(text: &str, width: usize) -> &str {
+    let mut used = 0;
+    for (index, grapheme) in text.grapheme_indices(true) {
+        used += grapheme.width();
+        if used > width {
+            return &text[..index];
+        }
+    }
+    text
+}
+
+/// The longest end of `text` that is at most `width` columns wide.
+fn suffix(text: &str, width: usize) -> &str {
+    let mut used = 0;
+    for (index, grapheme) in text.grapheme_indices(true).rev() {
+        used += grapheme.width();
+        if used > width {
+            return &text[index + grapheme.len()..];
+        }
+    }
+    text
+}
+
+/// Shortens `text` to at most `width` columns by replacing whole graphemes with an ellipsis.
+/// Text that fits is returned as is, nothing fits into less than the ellipsis.
+pub fn truncate(text: &str, width: usize, ellipsis: Ellipsis) -> Cow<'_, str> {
+    if text.width() <= width {
+        return Cow::Borrowed(text);
+    }
+    let Some(room) = width.checked_sub(ELLIPSIS.width()) else {
+        return Cow::Owned(String::new());
+    };
+    match ellipsis {
+        Ellipsis::End => Cow::Owned(format!("{}{ELLIPSIS}", prefix(text, room).trim_end())),
+        Ellipsis::Middle => {
+            let end = suffix(text, room / 2);
+            let start = prefix(text, room - end.width());
+            Cow::Owned(format!(
+                "{}{ELLIPSIS}{}",
+                start.trim_end(),
+                end.trim_start()
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use unicode_width::UnicodeWidthStr;
+
+    use super::{truncate, Ellipsis, Truncate};
+
+    #[test]
+    fn end() {
+        assert_eq!(
+            "Artist - Title",
+            truncate("Artist - Title", 14, Ellipsis::End)
+        );
+        assert_eq!("Artist…", truncate("Artist - Title", 8, Ellipsis::End));
+        assert_eq!("…", truncate("Artist - Title", 1, Ellipsis::End));
+        assert_eq!("", truncate("Artist - Title", 0, Ellipsis::End));
+        // wide characters take two columns and are not split
+        assert_eq!(
+            "坂本龍…",
+            truncate("坂本龍一 - 戦場のメリークリスマス", 8, Ellipsis::End)
+        );
+        assert_eq!(
+            "坂本…",
+            truncate("坂本龍一 - 戦場のメリークリスマス", 6, Ellipsis::End)
+        );
+        // combining characters and emoji sequences stay together
+        assert_eq!(
+            "Beyonce\u{301}…",
+            truncate("Beyonce\u{301} - Halo", 9, Ellipsis::End)
+        );
+        assert_eq!("a👩‍👩‍👧…", truncate("a👩‍👩‍👧👩‍👩‍👧", 4, Ellipsis::End));
+        assert_eq!("a…", truncate("a👩‍👩‍👧👩‍👩‍👧", 3, Ellipsis::End));
+        assert_eq!("Сплин…", truncate("Сплин - Выхода нет", 6, Ellipsis::End));
+    }
+
+    #[test]
+    fn middle() {
+        assert_eq!("Arti…tle", truncate("Artist - Title", 8, Ellipsis::Middle));
+        assert_eq!("A…e", truncate("Artist - Title", 3, Ellipsis::Middle));
+        assert_eq!("…", truncate("Artist - Title", 1, Ellipsis::Middle));
+        assert_eq!(
+            "",
+            Truncate {
+                width: 0,
+                ellipsis: Ellipsis::Middle
+            }
+            .apply("Artist - Title".into())
+        );
+        let truncated = truncate("坂本龍一 - 戦場のメリークリスマス", 9, Ellipsis::Middle);
+        assert_eq!("坂本…マス", truncated);
+        assert!(truncated.width() <= 9);
+    }
+}
@@ -20,8 +20,18 @@ use tokio::{
     sync::Mutex,
     try_join,
 };
+use mpris_dbus::hide::AutoHide;
 use tracing::{info, warn};
 
+use super::{
+    cleanup::Cleanup,
+    marquee::{scrolling, Scroll},
+    players::Players,
+    track_name,
+    truncate::Truncate,
+    Display,
+};
+
 use zbus::{
     export::futures_util::{FutureExt, StreamExt},
     Connection,
@@ -141,7 +151,14 @@ fn to_listener(fd: FileDescriptor) -> io::Result<UnixListener> {
     UnixListener::from_std(listener)
 }
 
-pub async fn waybar(hide: bool) -> anyhow::Result<()> {
+pub async fn waybar(
+    hide: bool,
+    auto_hide: AutoHide,
+    scroll: Option<Scroll>,
+    truncate: Option<Truncate>,
+    cleanup: Cleanup,
+    mut players: Players,
+) -> anyhow::Result<()> {
     let mut sockets = receive_descriptors(false).context("receiving sockets from systemd")?;
     if 6 != sockets.len() {
         Err(anyhow!(
@@ -156,8 +173,10 @@ pub async fn waybar(hide: bool) -> anyhow::Result<()> {
     let next_player = Output::new(to_listener(sockets.pop().unwrap())?);
     let prev_player = Output::new(to_listener(sockets.pop().unwrap())?);
 
-    let stream =
-        mpris_dbus::hide::hidden_active_player_info(&Connection::session().await?, hide).await?;
+    let conn = Connection::session().await?;
+    let stream = mpris_dbus::hide::hidden_active_player_info_with(&conn, hide, auto_hide)
+        .await?
+        .map(move |info| cleanup.info(info));
 
     libsystemd::daemon::notify(false, &[NotifyState::Ready])?;
     info!("connection established");
@@ -169,34 +188,24 @@ pub async fn waybar(hide: bool) -> anyhow::Result<()> {
         flatten(spawn(next_player.clone().listen())),
         flatten(spawn(prev_player.clone().listen())),
         spawn(async move {
-            let mut stream = pin!(stream);
-            while let Some(info) = stream.next().await {
+            let mut stream = pin!(scrolling(stream, scroll));
+            let mut last = None;
+            while let Some((info, offset)) = players.next(&mut stream, &mut last).await {
+                players.load(&conn, &info);
+                let display = Display {
+                    scroll,
+                    offset,
+                    truncate,
+                };
                 let infos = match info.as_ref() {
                     Some(info) => match info.as_ref() {
                         Some(info) => match info.as_ref() {
                             Ok((names, info)) => {
-                                let mut artist: Option<&str> = info
-                                    .metadata
-                                    .get("xesam:artist")
-                                    .and_then(|i| i.downcast_ref());
-                                let title: Option<&str> = info
-                                    .metadata
-                                    .get("xesam:title")
-                                    .and_then(|i| i.downcast_ref());
-                                if artist.is_some()
-                                    && title.is_some()
-                                    && title.unwrap().starts_with(artist.unwrap())
-                                {
-                                    artist = None;
-                                }
-                                let name = match (artist, title) {
-                                    (Some(name), Some(title)) => {
-                                        Cow::from(format!("{name} - {title}"))
-                                    }
-                                    (Some(name), None) => Cow::from(name),
-                                    (None, Some(title)) => Cow::from(title),
-                                    (None, None) => Cow::from(""),
-                                };
+                                let name = track_name(info);
+                                let player = names
+                                    .first()
+                                    .map(|name| players.get(name))
+                                    .unwrap_or_default();
                                 let (play, play_tooltip) = match info.playback_status {
                                     mpris_dbus::proxies::PlaybackStatus::Playing => ("⏸", "pause"),
                                     mpris_dbus::proxies::PlaybackStatus::Paused => ("▶", "play"),
@@ -242,8 +251,11 @@ pub async fn waybar(hide: bool) -> anyhow::Result<()> {
                                     prev_player,
                                     next_player,
                                     title: Info {
-                                        text: Some(name.clone()),
-                                        tooltip: Some(name),
+                                        text: Some(
+                                            format!("{} {}", player.glyph, display.name(name.clone()))
+                                                .into(),
+                                        ),
+                                        tooltip: Some(format!("{}: {name}", player.name).into()),
                                         class: None,
                                     },
                                     play_pause: Info {
@@ -261,7 +273,14 @@ pub async fn waybar(hide: bool) -> anyhow::Result<()> {
                                     title: Info {
                                         text: Some(format!("{e}").into()),
                                         tooltip: Some(format!("{e}").into()),
-                                        class: None,
+                                        class: Some(
+                                            if e.is_transient() {
+                                                "unavailable"
+                                            } else {
+                                                "error"
+                                            }
+                                            .into(),
+                                        ),
                                     },
                                     ..Default::default()
                                 }
@@ -1,42 +1,31 @@
 use std::borrow::Cow;
 
-use super::Info;
+use super::{players::Players, track_name, Display, Info};
 
 struct YambarInfo<'a> {
     show: bool,
     switch: bool,
     text: Cow<'a, str>,
+    player: &'a str,
+    player_glyph: &'a str,
+    player_icon: &'a str,
     next: bool,
     prev: bool,
     show_play: bool,
     play_pause: &'static str,
 }
 
-pub fn yambar(info: Info) {
+pub fn yambar(info: Info, players: &Players, display: Display) {
+    let player;
     let info = match info.as_ref() {
         Some(info) => match info.as_ref() {
             Some(info) => match info.as_ref() {
-                Ok((_, info)) => {
-                    let mut artist: Option<&str> = info
-                        .metadata
-                        .get("xesam:artist")
-                        .and_then(|i| i.downcast_ref());
-                    let title: Option<&str> = info
-                        .metadata
-                        .get("xesam:title")
-                        .and_then(|i| i.downcast_ref());
-                    if artist.is_some()
-                        && title.is_some()
-                        && title.unwrap().starts_with(artist.unwrap())
-                    {
-                        artist = None;
-                    }
-                    let name = match (artist, title) {
-                        (Some(name), Some(title)) => Cow::from(format!("{name} - {title}")),
-                        (Some(name), None) => Cow::from(name),
-                        (None, Some(title)) => Cow::from(title),
-                        (None, None) => Cow::from(""),
-                    };
+                Ok((names, info)) => {
+                    let name = display.name(track_name(info));
+                    player = names
+                        .first()
+                        .map(|name| players.get(name))
+                        .unwrap_or_default();
                     let play = match info.playback_status {
                         mpris_dbus::proxies::PlaybackStatus::Playing => "⏸",
                         mpris_dbus::proxies::PlaybackStatus::Paused => "▶",
@@ -47,6 +36,9 @@ pub fn yambar(info: Info) {
                         show: true,
                         switch: true,
                         text: name,
+                        player: &player.name,
+                        player_glyph: &player.glyph,
+                        player_icon: player.icon.as_deref().unwrap_or_default(),
                         prev: info.can_go_previous,
                         next: info.can_go_next,
                         show_play: true,
@@ -57,6 +49,9 @@ pub fn yambar(info: Info) {
                     show: true,
                     switch: false,
                     text: Cow::Owned(format!("{e}")),
+                    player: "",
+                    player_glyph: "",
+                    player_icon: "",
                     prev: false,
                     next: false,
                     show_play: false,
@@ -67,6 +62,9 @@ pub fn yambar(info: Info) {
                 show: true,
                 switch: false,
                 text: Cow::Borrowed("No Player"),
+                player: "",
+                player_glyph: "",
+                player_icon: "",
                 prev: false,
                 next: false,
                 show_play: false,
@@ -77,6 +75,9 @@ pub fn yambar(info: Info) {
             show: false,
             switch: false,
             text: Cow::Borrowed(""),
+            player: "",
+            player_glyph: "",
+            player_icon: "",
             next: false,
             prev: false,
             show_play: false,
@@ -88,11 +89,23 @@ pub fn yambar(info: Info) {
 mpris_show|bool|{}
 mpris_switch|bool|{}
 mpris_text|string|{}
+mpris_player|string|{}
+mpris_player_glyph|string|{}
+mpris_player_icon|string|{}
 mpris_next|bool|{}
 mpris_prev|bool|{}
 mpris_show_play|bool|{}
 mpris_play_pause|string|{}
 ",
-        info.show, info.switch, info.text, info.next, info.prev, info.show_play, info.play_pause
+        info.show,
+        info.switch,
+        info.text,
+        info.player,
+        info.player_glyph,
+        info.player_icon,
+        info.next,
+        info.prev,
+        info.show_play,
+        info.play_pause
     );
 }
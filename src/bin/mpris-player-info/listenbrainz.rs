@@ -0,0 +1,578 @@
+use std::{
+    fs::{self, create_dir_all, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+use tokio::{select, spawn, sync::Notify, task::spawn_blocking, time::sleep};
+use tracing::{info, warn};
+
+use crate::{dirs::data_dir, history::Play};
+
+/// Listens submitted at most in one request.
+const MAX_BATCH: usize = 100;
+/// Delay before the first retry of a failed submission, doubled on every failure.
+const MIN_RETRY: Duration = Duration::from_secs(30);
+const MAX_RETRY: Duration = Duration::from_secs(60 * 60);
+
+fn default_url() -> String {
+    "https://api.listenbrainz.org".to_string()
+}
+
+/// The `[listenbrainz]` section of the config file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Settings {
+    /// Root of the API, the listens are posted to `<url>/1/submit-listens`.
+    #[serde(default = "default_url")]
+    pub url: String,
+    /// The user token.
+    pub token: String,
+}
+
+impl Settings {
+    fn submit_url(&self) -> String {
+        format!("{}/1/submit-listens", self.url.trim_end_matches('/'))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct AdditionalInfo {
+    media_player: String,
+    submission_client: String,
+    submission_client_version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    duration_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct TrackMetadata {
+    artist_name: String,
+    track_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    release_name: Option<String>,
+    additional_info: AdditionalInfo,
+}
+
+/// A listen as expected by ListenBrainz, also a line of the spool file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Listen {
+    listened_at: u64,
+    track_metadata: TrackMetadata,
+}
+
+impl Listen {
+    /// The listen of `play`, `None` without an artist as ListenBrainz requires one.
+    pub fn new(play: &Play) -> Option<Self> {
+        let player = play
+            .player
+            .strip_prefix("org.mpris.MediaPlayer2.")
+            .unwrap_or(&play.player);
+        // drop the instance of names like vlc.instance1234
+        let player = player.split('.').next().unwrap_or(player);
+        Some(Listen {
+            listened_at: play.time,
+            track_metadata: TrackMetadata {
+                artist_name: play.artist.clone().filter(|artist| !artist.is_empty())?,
+                track_name: play.title.clone(),
+                release_name: play.album.clone().filter(|album| !album.is_empty()),
+                additional_info: AdditionalInfo {
+                    media_player: player.to_string(),
+                    submission_client: "mpris-player-info".to_string(),
+                    submission_client_version: env!("CARGO_PKG_VERSION").to_string(),
+                    duration_ms: play.length.map(|length| (length * 1000.0).round() as u64),
+                },
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ListenType {
+    Single,
+    Import,
+}
+
+/// Body of `submit-listens`.
+#[derive(Debug, Serialize)]
+struct Submission<'a> {
+    listen_type: ListenType,
+    payload: &'a [Listen],
+}
+
+impl<'a> Submission<'a> {
+    fn new(payload: &'a [Listen]) -> Self {
+        Submission {
+            // single only allows one listen
+            listen_type: match payload.len() {
+                1 => ListenType::Single,
+                _ => ListenType::Import,
+            },
+            payload,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+/// The HTTP client used for submissions, replaceable to test against other servers.
+pub trait Http: Send + Sync {
+    /// Posts the json `body` to `url` with the ListenBrainz `token`. Errors are only
+    /// returned if there is no response.
+    fn post(&self, url: &str, token: &str, body: &str) -> anyhow::Result<Response>;
+}
+
+pub struct Ureq(ureq::Agent);
+
+impl Default for Ureq {
+    fn default() -> Self {
+        Ureq(
+            ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(30))
+                .user_agent(concat!("mpris-player-info/", env!("CARGO_PKG_VERSION")))
+                .build(),
+        )
+    }
+}
+
+impl Http for Ureq {
+    fn post(&self, url: &str, token: &str, body: &str) -> anyhow::Result<Response> {
+        let response = self
+            .0
+            .post(url)
+            .set("Authorization", &format!("Token {token}"))
+            .set("Content-Type", "application/json")
+            .send_string(body);
+        let response = match response {
+            Ok(response) => response,
+            Err(ureq::Error::Status(_, response)) => response,
+            Err(ureq::Error::Transport(e)) => return Err(e.into()),
+        };
+        Ok(Response {
+            status: response.status(),
+            body: response.into_string().unwrap_or_default(),
+        })
+    }
+}
+
+/// Takes an exclusive advisory lock on `path`, released when the file is dropped.
+fn lock(path: &Path) -> anyhow::Result<File> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("opening {}", path.display()))?;
+    file.lock()
+        .with_context(|| format!("locking {}", path.display()))?;
+    Ok(file)
+}
+
+/// Listens waiting for submission, one json object per line. Listens are appended on one
+/// end and submitted from the other, so the file survives restarts and being offline.
+///
+/// The file may be shared by several processes: appending and removing listens is serialized
+/// by the lock file `<path>.lock`, and only the process holding `<path>.submitting` submits
+/// listens, so no listen is submitted twice.
+pub struct Spool {
+    path: PathBuf,
+}
+
+impl Spool {
+    pub fn new(path: PathBuf) -> Self {
+        Spool { path }
+    }
+
+    /// `$XDG_DATA_HOME/mpris-player-info/listenbrainz-spool.jsonl`
+    pub fn default_path() -> anyhow::Result<PathBuf> {
+        Ok(data_dir()?.join("listenbrainz-spool.jsonl"))
+    }
+
+    /// Locks `<path>.<extension>`, creating the data dir if needed.
+    fn lock(&self, extension: &str) -> anyhow::Result<File> {
+        if let Some(dir) = self.path.parent() {
+            create_dir_all(dir).context("creating data dir")?;
+        }
+        lock(&self.path.with_extension(format!("jsonl.{extension}")))
+    }
+
+    pub fn push(&self, listen: &Listen) -> anyhow::Result<()> {
+        let mut line = serde_json::to_string(listen).context("serializing listen")?;
+        line.push('\n');
+        let _lock = self.lock("lock")?;
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .with_context(|| format!("writing {}", self.path.display()))
+    }
+
+    fn lines(&self) -> anyhow::Result<Vec<String>> {
+        match fs::read_to_string(&self.path) {
+            Ok(content) => Ok(content
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e).with_context(|| format!("reading {}", self.path.display())),
+        }
+    }
+
+    /// Number of listens waiting for submission.
+    pub fn len(&self) -> anyhow::Result<usize> {
+        let _lock = self.lock("lock")?;
+        Ok(self.lines()?.len())
+    }
+
+    /// Removes the first `count` listens, keeping the ones appended in the meantime.
+    fn remove(&self, count: usize) -> anyhow::Result<()> {
+        let _lock = self.lock("lock")?;
+        let rest: String = self
+            .lines()?
+            .into_iter()
+            .skip(count)
+            .map(|line| line + "\n")
+            .collect();
+        let tmp = self.path.with_extension("jsonl.tmp");
+        fs::write(&tmp, rest)
+            .and_then(|()| fs::rename(&tmp, &self.path))
+            .with_context(|| format!("writing {}", self.path.display()))
+    }
+
+    /// Submits all spooled listens, returning how many were accepted. On errors the
+    /// remaining listens stay in the spool to be retried later. Batches the server rejects
+    /// are split until only the invalid listens are dropped.
+    pub fn submit(&self, http: &dyn Http, settings: &Settings) -> anyhow::Result<usize> {
+        // waits for another process submitting the same spool
+        let _submitting = self.lock("submitting")?;
+        let (mut submitted, mut batch) = (0, MAX_BATCH);
+        loop {
+            let lines: Vec<String> = {
+                let _lock = self.lock("lock")?;
+                self.lines()?.into_iter().take(batch).collect()
+            };
+            if lines.is_empty() {
+                return Ok(submitted);
+            }
+            let listens: Vec<Listen> = lines
+                .iter()
+                .filter_map(|line| match serde_json::from_str(line) {
+                    Ok(listen) => Some(listen),
+                    Err(e) => {
+                        warn!("dropping malformed spooled listen {line:?}: {e}");
+                        None
+                    }
+                })
+                .collect();
+            if !listens.is_empty() {
+                let body =
+                    serde_json::to_string(&Submission::new(&listens)).context("serializing")?;
+                let response = http
+                    .post(&settings.submit_url(), &settings.token, &body)
+                    .context("submitting listens")?;
+                match response.status {
+                    200..=299 => submitted += listens.len(),
+                    // a single invalid listen rejects the whole batch
+                    400 if listens.len() > 1 => {
+                        batch = lines.len() / 2;
+                        continue;
+                    }
+                    // the listen is invalid and would be rejected forever
+                    400 => warn!(
+                        "dropping listen rejected by ListenBrainz: {}",
+                        response.body
+                    ),
+                    status => {
+                        return Err(anyhow!(
+                            "submitting listens failed with status {status}: {}",
+                            response.body
+                        ))
+                    }
+                }
+            }
+            self.remove(lines.len())?;
+            batch = MAX_BATCH;
+        }
+    }
+}
+
+/// Spools the recorded plays and submits them in the background.
+pub struct Scrobbler {
+    spool: Arc<Spool>,
+    submit: Arc<Notify>,
+}
+
+impl Scrobbler {
+    /// Starts submitting the spool, must be called within the tokio runtime.
+    pub fn start(settings: Settings, spool: Spool, http: Arc<dyn Http>) -> Self {
+        let spool = Arc::new(spool);
+        let submit = Arc::new(Notify::new());
+        let (task_spool, task_submit) = (spool.clone(), submit.clone());
+        spawn(async move {
+            let mut retry = MIN_RETRY;
+            loop {
+                let (spool, http, settings) = (task_spool.clone(), http.clone(), settings.clone());
+                let result = spawn_blocking(move || spool.submit(&*http, &settings)).await;
+                match result.map_err(anyhow::Error::from).and_then(|r| r) {
+                    Ok(submitted) => {
+                        if submitted > 0 {
+                            info!("submitted {submitted} listens");
+                        }
+                        retry = MIN_RETRY;
+                        task_submit.notified().await;
+                    }
+                    Err(e) => {
+                        warn!("{e:#}, retrying in {retry:?}");
+                        select! {
+                            _ = sleep(retry) => {}
+                            _ = task_submit.notified() => {}
+                        }
+                        retry = (retry * 2).min(MAX_RETRY);
+                    }
+                }
+            }
+        });
+        Scrobbler { spool, submit }
+    }
+
+    /// Spools the listen of `play` and triggers a submission.
+    pub fn listen(&self, play: &Play) -> anyhow::Result<()> {
+        let Some(listen) = Listen::new(play) else {
+            info!("not submitting {play:?} without an artist");
+            return Ok(());
+        };
+        self.spool.push(&listen)?;
+        self.submit.notify_one();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        env, fs,
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        path::PathBuf,
+        process,
+        thread::{self, JoinHandle},
+    };
+
+    use serde_json::json;
+
+    use super::{Listen, Settings, Spool, Ureq};
+    use crate::history::Play;
+
+    fn play(title: &str) -> Play {
+        Play {
+            time: 1700000000,
+            player: "org.mpris.MediaPlayer2.vlc.instance42".to_string(),
+            title: title.to_string(),
+            artist: Some("Artist".to_string()),
+            album: None,
+            length: Some(201.5),
+            listened: 150.0,
+        }
+    }
+
+    /// A directory removed together with its content when dropped.
+    struct TempDir(PathBuf);
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// A spool in a directory of its own, which keeps its lock and temporary files as well.
+    fn spool(name: &str) -> (TempDir, Spool) {
+        let dir = env::temp_dir().join(format!("mpris-player-info-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let spool = Spool::new(dir.join("listens.jsonl"));
+        (TempDir(dir), spool)
+    }
+
+    struct Request {
+        line: String,
+        authorization: String,
+        body: String,
+    }
+
+    /// Serves one connection for every status, returning the requests.
+    fn serve(statuses: Vec<u16>) -> (String, JoinHandle<Vec<Request>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            statuses
+                .into_iter()
+                .map(|status| {
+                    let (stream, _) = listener.accept().unwrap();
+                    let mut reader = BufReader::new(stream);
+                    let mut request = String::new();
+                    reader.read_line(&mut request).unwrap();
+                    let (mut authorization, mut length) = (String::new(), 0);
+                    loop {
+                        let mut header = String::new();
+                        reader.read_line(&mut header).unwrap();
+                        let header = header.trim_end();
+                        if header.is_empty() {
+                            break;
+                        }
+                        let (name, value) = header.split_once(": ").unwrap();
+                        match name.to_ascii_lowercase().as_str() {
+                            "authorization" => authorization = value.to_string(),
+                            "content-length" => length = value.parse().unwrap(),
+                            _ => {}
+                        }
+                    }
+                    let mut body = vec![0; length];
+                    reader.read_exact(&mut body).unwrap();
+                    write!(
+                        reader.get_mut(),
+                        "HTTP/1.1 {status} X\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{{}}"
+                    )
+                    .unwrap();
+                    Request {
+                        line: request.trim_end().to_string(),
+                        authorization,
+                        body: String::from_utf8(body).unwrap(),
+                    }
+                })
+                .collect()
+        });
+        (url, server)
+    }
+
+    fn settings(url: String) -> Settings {
+        Settings {
+            url,
+            token: "secret".to_string(),
+        }
+    }
+
+    #[test]
+    fn payload() {
+        let listen = Listen::new(&play("Title")).unwrap();
+        assert_eq!(
+            json!({
+                "listened_at": 1700000000,
+                "track_metadata": {
+                    "artist_name": "Artist",
+                    "track_name": "Title",
+                    "additional_info": {
+                        "media_player": "vlc",
+                        "submission_client": "mpris-player-info",
+                        "submission_client_version": env!("CARGO_PKG_VERSION"),
+                        "duration_ms": 201500,
+                    },
+                },
+            }),
+            serde_json::to_value(&listen).unwrap()
+        );
+        let untitled = Play {
+            artist: None,
+            ..play("Title")
+        };
+        assert_eq!(None, Listen::new(&untitled));
+        let settings: Settings = toml::from_str("token = \"secret\"").unwrap();
+        assert_eq!(
+            "https://api.listenbrainz.org/1/submit-listens",
+            settings.submit_url()
+        );
+    }
+
+    #[test]
+    fn retry() {
+        let (_dir, spool) = spool("retry");
+        for title in ["a", "b"] {
+            spool.push(&Listen::new(&play(title)).unwrap()).unwrap();
+        }
+        let (url, server) = serve(vec![503, 200, 200]);
+        let settings = settings(url);
+        // server errors keep the listens
+        assert!(spool.submit(&Ureq::default(), &settings).is_err());
+        assert_eq!(2, spool.len().unwrap());
+        assert_eq!(2, spool.submit(&Ureq::default(), &settings).unwrap());
+        assert_eq!(0, spool.len().unwrap());
+        spool.push(&Listen::new(&play("c")).unwrap()).unwrap();
+        assert_eq!(1, spool.submit(&Ureq::default(), &settings).unwrap());
+        let requests = server.join().unwrap();
+        for request in &requests {
+            assert_eq!("POST /1/submit-listens HTTP/1.1", request.line);
+            assert_eq!("Token secret", request.authorization);
+        }
+        let bodies: Vec<serde_json::Value> = requests
+            .iter()
+            .map(|request| serde_json::from_str(&request.body).unwrap())
+            .collect();
+        assert_eq!(bodies[0], bodies[1]);
+        assert_eq!("import", bodies[1]["listen_type"]);
+        assert_eq!("b", bodies[1]["payload"][1]["track_metadata"]["track_name"]);
+        assert_eq!("single", bodies[2]["listen_type"]);
+        assert_eq!("c", bodies[2]["payload"][0]["track_metadata"]["track_name"]);
+    }
+
+    #[test]
+    fn offline() {
+        let (_dir, spool) = spool("offline");
+        spool.push(&Listen::new(&play("a")).unwrap()).unwrap();
+        // nothing listens on the port anymore
+        let url = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}", listener.local_addr().unwrap())
+        };
+        assert!(spool.submit(&Ureq::default(), &settings(url)).is_err());
+        assert_eq!(1, spool.len().unwrap());
+    }
+
+    #[test]
+    fn rejected() {
+        let (_dir, spool) = spool("rejected");
+        let line = |title| serde_json::to_string(&Listen::new(&play(title)).unwrap()).unwrap();
+        fs::write(
+            &spool.path,
+            format!("garbage\n{}\n{}\n", line("a"), line("b")),
+        )
+        .unwrap();
+        // a and b next to the garbage, a and b again once the garbage is dropped, then a alone,
+        // which is dropped, and b alone
+        let (url, server) = serve(vec![400, 400, 400, 200]);
+        // invalid listens are dropped instead of being retried forever, the valid ones of the
+        // rejected batch are still submitted
+        assert_eq!(1, spool.submit(&Ureq::default(), &settings(url)).unwrap());
+        assert_eq!(0, spool.len().unwrap());
+        let requests = server.join().unwrap();
+        let last: serde_json::Value = serde_json::from_str(&requests[3].body).unwrap();
+        assert_eq!("b", last["payload"][0]["track_metadata"]["track_name"]);
+    }
+
+    #[test]
+    fn shared() {
+        let (_dir, spool) = spool("shared");
+        let path = spool.path.clone();
+        for title in ["a", "b"] {
+            spool.push(&Listen::new(&play(title)).unwrap()).unwrap();
+        }
+        let (url, server) = serve(vec![200]);
+        // another process submitting the same spool at the same time
+        let other = thread::spawn({
+            let url = url.clone();
+            move || Spool::new(path).submit(&Ureq::default(), &settings(url))
+        });
+        let submitted = spool.submit(&Ureq::default(), &settings(url)).unwrap();
+        assert_eq!(2, submitted + other.join().unwrap().unwrap());
+        assert_eq!(1, server.join().unwrap().len());
+    }
+}
@@ -0,0 +1,55 @@
+use std::{path::PathBuf, pin::pin};
+
+use anyhow::Context;
+use clap::Args;
+use mpris_dbus::lyrics::active_lyrics;
+use tracing::{warn, Level};
+use tracing_subscriber::EnvFilter;
+use zbus::{export::futures_util::StreamExt, Connection};
+
+#[derive(Args, Clone)]
+pub struct Options {
+    ///directory with lyrics named like the music files or `<artist> - <title>.lrc`, can be
+    ///given multiple times. Lyrics next to the music files are always used
+    #[arg(short, long = "dir")]
+    dirs: Vec<PathBuf>,
+}
+
+pub fn main(options: Options) -> anyhow::Result<()> {
+    let level = if cfg!(debug_assertions) {
+        Level::DEBUG
+    } else {
+        Level::INFO
+    };
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::builder()
+                .with_default_directive(level.into())
+                .from_env_lossy(),
+        )
+        .with_writer(std::io::stderr)
+        .init();
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("building tokio runtime")?
+        .block_on(async {
+            let conn = Connection::session()
+                .await
+                .context("connecting to session dbus")?;
+            let mut lines = pin!(active_lyrics(conn, options.dirs)
+                .await
+                .context("following the lyrics")?);
+            while let Some(line) = lines.next().await {
+                // one line per update, empty while there is nothing to show
+                match line {
+                    Ok(line) => println!("{}", line.unwrap_or_default()),
+                    Err(e) => {
+                        warn!("following the lyrics failed: {e}");
+                        println!()
+                    }
+                }
+            }
+            Ok(())
+        })
+}
@@ -5,6 +5,10 @@ use std::{ffi::OsString, fs::File};
 use anyhow::Result;
 use clap::{Parser, Subcommand, CommandFactory};
 use clap_complete::{Shell, generate};
+#[cfg(feature = "info_base")]
+use mpris_dbus::hide::AutoHide;
+#[cfg(feature = "info_base")]
+use std::{path::PathBuf, time::Duration};
 
 #[cfg(feature = "toggle_hide")]
 mod toggle_hide;
@@ -18,6 +22,31 @@ mod waybar_cat;
 #[cfg(feature = "hide_dbus_server")]
 mod hide_server;
 
+#[cfg(feature = "config")]
+mod config;
+
+#[cfg(any(feature = "config", feature = "history", feature = "hide_dbus_server"))]
+mod dirs;
+
+#[cfg(feature = "hooks")]
+mod hooks;
+
+#[cfg(feature = "history")]
+mod history;
+
+#[cfg(feature = "listenbrainz")]
+mod listenbrainz;
+
+#[cfg(feature = "info_lyrics")]
+mod lyrics;
+
+// the library test fixtures, they find the library modules under `crate::`
+#[cfg(all(test, any(feature = "hooks", feature = "info_base")))]
+use mpris_dbus::{player_info, proxies};
+#[cfg(all(test, any(feature = "hooks", feature = "info_base")))]
+#[path = "../../test_util.rs"]
+mod test_util;
+
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 #[command(propagate_version = true)]
@@ -30,10 +59,16 @@ struct Cli {
 enum Command {
     #[cfg(feature = "toggle_hide")]
     ///toggle hide status
-    ToggleHide,
+    ToggleHide(toggle_hide::Target),
+    #[cfg(feature = "toggle_hide")]
+    ///show, hide or query hide status
+    Hide {
+        #[command(subcommand)]
+        action: toggle_hide::Action,
+    },
     #[cfg(feature = "hide_dbus_server")]
     ///run hide status server
-    HideServer,
+    HideServer(hide_server::Options),
     #[cfg(feature = "info_waybar_cat")]
     ///cat waybar info from one of the sockets
     InfoWaybarCat {
@@ -47,6 +82,48 @@ enum Command {
         bar: info::Bars,
         #[arg(short = 'd', long)]
         hidden: bool,
+        ///hide after the player has been paused or stopped for this many minutes
+        #[arg(long, value_name = "MINUTES")]
+        hide_idle: Option<u64>,
+        ///hide while the player reports no title
+        #[arg(long)]
+        hide_untitled: bool,
+        ///scroll names wider than this many columns instead of truncating them
+        #[arg(
+            long,
+            value_name = "COLUMNS",
+            value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+        )]
+        scroll: Option<usize>,
+        ///milliseconds between two scroll steps
+        #[arg(
+            long,
+            value_name = "MILLISECONDS",
+            default_value_t = 300,
+            value_parser = clap::value_parser!(u64).range(1..)
+        )]
+        scroll_interval: u64,
+        ///truncate names wider than this many columns
+        #[arg(long, value_name = "COLUMNS")]
+        truncate: Option<usize>,
+        ///where to put the ellipsis of truncated names
+        #[arg(long, value_enum, default_value_t)]
+        ellipsis: info::truncate::Ellipsis,
+        ///config file with the title cleanup rules, defaults to $XDG_CONFIG_HOME/mpris-player-info/config.toml
+        #[arg(short, long)]
+        config: Option<PathBuf>,
+    },
+    #[cfg(feature = "info_lyrics")]
+    ///print the current line of the lyrics of the active player
+    Lyrics(lyrics::Options),
+    #[cfg(feature = "hooks")]
+    ///run the hooks of the config file on player events
+    Hooks(hooks::Options),
+    #[cfg(feature = "history")]
+    ///record and show the listening history
+    History {
+        #[command(subcommand)]
+        action: history::Action,
     },
     ///generate autocomplete scripts
     Autocomplete{
@@ -59,13 +136,44 @@ fn main() -> Result<()> {
     let cli = Cli::parse();
     match cli.command {
         #[cfg(feature = "toggle_hide")]
-        Command::ToggleHide => toggle_hide::main(),
+        Command::ToggleHide(target) => toggle_hide::main(toggle_hide::Action::Toggle(target)),
+        #[cfg(feature = "toggle_hide")]
+        Command::Hide { action } => toggle_hide::main(action),
         #[cfg(feature = "hide_dbus_server")]
-        Command::HideServer => hide_server::main(),
+        Command::HideServer(options) => hide_server::main(options),
         #[cfg(feature = "info_waybar_cat")]
         Command::InfoWaybarCat { file } => waybar_cat::main(file),
         #[cfg(feature = "info_base")]
-        Command::Info { bar, hidden } => info::main(bar, hidden),
+        Command::Info {
+            bar,
+            hidden,
+            hide_idle,
+            hide_untitled,
+            scroll,
+            scroll_interval,
+            truncate,
+            ellipsis,
+            config,
+        } => info::main(
+            bar,
+            hidden,
+            AutoHide {
+                idle: hide_idle.map(|minutes| Duration::from_secs(minutes * 60)),
+                untitled: hide_untitled,
+            },
+            scroll.map(|width| info::marquee::Scroll {
+                width,
+                interval: Duration::from_millis(scroll_interval),
+            }),
+            truncate.map(|width| info::truncate::Truncate { width, ellipsis }),
+            config.as_deref(),
+        ),
+        #[cfg(feature = "info_lyrics")]
+        Command::Lyrics(options) => lyrics::main(options),
+        #[cfg(feature = "hooks")]
+        Command::Hooks(options) => hooks::main(options),
+        #[cfg(feature = "history")]
+        Command::History { action } => history::main(action),
         Command::Autocomplete { shell, output } => {
             generate(shell, &mut Cli::command(), "mpris-player-info", &mut File::create(output).expect("opening output"));
             Ok(())
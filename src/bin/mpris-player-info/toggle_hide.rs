@@ -1,15 +1,104 @@
+use std::{pin::pin, process};
+
 use anyhow::Context;
-use mpris_dbus::proxies::HideStateProxy;
-use zbus::Connection;
+use clap::{Args, Subcommand};
+use mpris_dbus::{
+    hide::{hidden, hidden_players},
+    proxies::HideStateProxy,
+};
+use zbus::{export::futures_util::StreamExt, Connection};
+
+#[derive(Subcommand, Clone)]
+pub enum Action {
+    ///toggle the hide status
+    Toggle(Target),
+    ///show the output
+    Show(Target),
+    ///hide the output
+    Hide(Target),
+    ///print the hide status, exits with 0 if hidden and 1 if shown
+    Status(Target),
+    ///print every change of the hide status
+    Watch(Target),
+}
+
+#[derive(Args, Clone)]
+pub struct Target {
+    ///only apply to one player, either the full bus name or the part after org.mpris.MediaPlayer2.
+    #[arg(short, long)]
+    player: Option<String>,
+}
+
+fn status(hidden: bool) -> &'static str {
+    if hidden {
+        "hidden"
+    } else {
+        "shown"
+    }
+}
 
-pub fn main() -> anyhow::Result<()> {
+pub fn main(action: Action) -> anyhow::Result<()> {
     tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build().context("building tokio runtime")?
         .block_on(async {
             let conn = Connection::session().await.context("connecting to session dbus")?;
             let proxy = HideStateProxy::new(&conn).await.context("connecting to hide state server")?;
-            proxy.toggle().await.context("toggling hide state")?;
+            let hide = matches!(action, Action::Hide(_));
+            match action {
+                Action::Toggle(Target { player: Some(player) }) => {
+                    proxy
+                        .toggle_player(&player)
+                        .await
+                        .with_context(|| format!("toggling hide state of {player}"))?;
+                }
+                Action::Toggle(Target { player: None }) => {
+                    proxy.toggle().await.context("toggling hide state")?;
+                }
+                Action::Show(Target { player: Some(player) })
+                | Action::Hide(Target { player: Some(player) }) => {
+                    proxy
+                        .set_player_hidden(&player, hide)
+                        .await
+                        .with_context(|| format!("setting hide state of {player}"))?;
+                }
+                Action::Show(Target { player: None }) | Action::Hide(Target { player: None }) => {
+                    proxy.set_hidden(hide).await.context("setting hide state")?;
+                }
+                Action::Status(Target { player }) => {
+                    let hidden = match player {
+                        Some(player) => proxy
+                            .hidden_players()
+                            .await
+                            .context("reading hidden players")?
+                            .contains(&player),
+                        None => proxy.hidden().await.context("reading hide state")?,
+                    };
+                    println!("{}", status(hidden));
+                    if !hidden {
+                        process::exit(1);
+                    }
+                }
+                Action::Watch(Target { player: Some(player) }) => {
+                    let mut stream = pin!(hidden_players(&conn)
+                        .await
+                        .context("watching hidden players")?);
+                    let mut last = None;
+                    while let Some(players) = stream.next().await {
+                        let hidden = players.context("reading hidden players")?.contains(&player);
+                        if last != Some(hidden) {
+                            println!("{}", status(hidden));
+                            last = Some(hidden);
+                        }
+                    }
+                }
+                Action::Watch(Target { player: None }) => {
+                    let mut stream = pin!(hidden(&conn).await.context("watching hide state")?);
+                    while let Some(hidden) = stream.next().await {
+                        println!("{}", status(hidden.context("reading hide state")?));
+                    }
+                }
+            }
             Ok(())
         })
 }
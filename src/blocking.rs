@@ -0,0 +1,119 @@
+//! Synchronous versions of the streams for programs without an async runtime, built on
+//! [`zbus::blocking`].
+
+use std::pin::Pin;
+
+use zbus::{
+    blocking::{fdo::DBusProxy, Connection},
+    export::futures_util::{Stream, StreamExt},
+    names::BusName,
+};
+
+use crate::{
+    hide::{hidden_active_player_info_with, AutoHide, HiddenInfo},
+    player_info::{PlayerInfo, Properties},
+    proxies::{PlayerProxyBlocking, PlayerctldProxyBlocking},
+    util::block_on,
+    Error,
+};
+
+/// Wether `e` reports that the called service is not on the bus.
+fn is_missing(e: &zbus::Error) -> bool {
+    match e {
+        zbus::Error::MethodError(name, _, _) => matches!(
+            name.as_str(),
+            "org.freedesktop.DBus.Error.ServiceUnknown"
+                | "org.freedesktop.DBus.Error.NameHasNoOwner"
+        ),
+        zbus::Error::FDO(e) => matches!(
+            **e,
+            zbus::fdo::Error::ServiceUnknown(_) | zbus::fdo::Error::NameHasNoOwner(_)
+        ),
+        _ => false,
+    }
+}
+
+/// The most recently active player according to playerctld, `None` if there is no player.
+pub fn active_player(conn: &Connection) -> crate::Result<Option<String>> {
+    let missing = |e: zbus::Error| {
+        if is_missing(&e) {
+            Error::PlayerctldNotRunning
+        } else {
+            e.into()
+        }
+    };
+    let proxy = PlayerctldProxyBlocking::new(conn).map_err(missing)?;
+    Ok(proxy.player_names().map_err(missing)?.into_iter().next())
+}
+
+/// The current state of the player `name`.
+pub fn player_info(
+    name: impl Into<BusName<'static>>,
+    conn: &Connection,
+) -> crate::Result<PlayerInfo> {
+    let name = name.into();
+    if !DBusProxy::new(conn)?.name_has_owner(name.as_ref())? {
+        return Err(Error::PlayerVanished(name.to_string()));
+    }
+    let proxy = PlayerProxyBlocking::builder(conn)
+        .destination(name)?
+        .build()?;
+    Ok(Properties {
+        playback_status: proxy.playback_status(),
+        loop_status: proxy.loop_status(),
+        shuffle: proxy.shuffle(),
+        metadata: proxy.metadata(),
+        can_go_next: proxy.can_go_next(),
+        can_go_previous: proxy.can_go_previous(),
+        can_play: proxy.can_play(),
+        can_pause: proxy.can_pause(),
+    }
+    .into_info())
+}
+
+/// Iterator returned by [`hidden_active_player_info`].
+pub struct HiddenActivePlayerInfo {
+    conn: zbus::Connection,
+    stream: Option<Pin<Box<dyn Stream<Item = Option<HiddenInfo>>>>>,
+}
+
+impl HiddenActivePlayerInfo {
+    /// The connection the player is followed on, for example to control the player.
+    pub fn connection(&self) -> Connection {
+        self.conn.clone().into()
+    }
+}
+
+impl Iterator for HiddenActivePlayerInfo {
+    type Item = Option<HiddenInfo>;
+
+    /// Blocks until the info changes.
+    fn next(&mut self) -> Option<Self::Item> {
+        block_on(self.stream.as_mut()?.next())
+    }
+}
+
+impl Drop for HiddenActivePlayerInfo {
+    fn drop(&mut self) {
+        // unsubscribing from signals spawns tasks, which needs the runtime as well
+        let stream = self.stream.take();
+        block_on(async move { drop(stream) });
+    }
+}
+
+/// Iterates over the items of [`crate::hide::hidden_active_player_info_with`] on an own
+/// connection to the session bus. The connection and the timers of the stream run on a
+/// runtime of this module, so they keep working between the calls to `next`.
+pub fn hidden_active_player_info(
+    default: bool,
+    rules: AutoHide,
+) -> crate::Result<HiddenActivePlayerInfo> {
+    block_on(async {
+        let conn = zbus::Connection::session().await?;
+        let stream = hidden_active_player_info_with(&conn, default, rules).await?;
+        Ok(HiddenActivePlayerInfo {
+            conn,
+            stream: Some(Box::pin(stream)),
+        })
+    })
+}
@@ -0,0 +1,575 @@
+//! C API for embedding the hidden active player info into programs written in other
+//! languages, e.g. the modules of a bar.
+//!
+//! The header and the pkg-config file are generated by `cargo cinstall --features capi`
+//! from [cargo-c](https://github.com/lu-zero/cargo-c). [`mpi_open`] follows the active
+//! player on a thread of its own, [`mpi_fd`] becomes readable whenever [`mpi_next_event`]
+//! has an event. Only the latest event is kept, so a slow consumer skips intermediate
+//! states instead of falling behind.
+//!
+//! Panics never unwind into the caller: functions that may panic report them like their
+//! other errors.
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_int, CStr, CString},
+    future::Future,
+    io::{Read, Write},
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::net::UnixStream,
+    },
+    panic::{self, AssertUnwindSafe},
+    pin::{pin, Pin},
+    ptr,
+    sync::{Arc, Condvar, Mutex},
+    task::{Context, Poll, Waker},
+    thread::{self, JoinHandle},
+};
+
+use zbus::{
+    blocking::Connection,
+    export::futures_util::{
+        future::{select, Either},
+        StreamExt,
+    },
+    CacheProperties,
+};
+
+use crate::{
+    hide::{hidden_active_player_info_with, AutoHide, HiddenInfo},
+    proxies::{PlaybackStatus, PlayerProxyBlocking},
+    util::block_on,
+};
+
+/// The player can go to the next track.
+pub const MPI_CAN_GO_NEXT: u32 = 1;
+/// The player can go to the previous track.
+pub const MPI_CAN_GO_PREVIOUS: u32 = 1 << 1;
+/// The player can start playing.
+pub const MPI_CAN_PLAY: u32 = 1 << 2;
+/// The player can pause.
+pub const MPI_CAN_PAUSE: u32 = 1 << 3;
+
+/// Playback status of an event.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// There is no player to show, the output is hidden or an error occurred.
+    None,
+    Stopped,
+    Paused,
+    Playing,
+}
+
+/// Action for [`mpi_control`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    PlayPause,
+    Play,
+    Pause,
+    Stop,
+    Next,
+    Previous,
+}
+
+/// State of the active player, freed with [`mpi_event_free`].
+#[derive(Debug, Default)]
+pub struct Event {
+    hidden: bool,
+    player: Option<CString>,
+    title: Option<CString>,
+    artist: Option<CString>,
+    status: Option<PlaybackStatus>,
+    capabilities: u32,
+    error: Option<CString>,
+    transient: bool,
+}
+
+fn c_string(s: &str) -> CString {
+    CString::new(s.replace('\0', "")).expect("all nul bytes are removed")
+}
+
+impl Event {
+    fn new(info: Option<HiddenInfo>) -> Self {
+        let Some(info) = info else {
+            return Event {
+                hidden: true,
+                ..Default::default()
+            };
+        };
+        match info {
+            Some(Ok((players, info))) => Event {
+                player: players.first().map(|name| c_string(name)),
+                title: info.metadata_text("xesam:title").map(|s| c_string(&s)),
+                artist: info.metadata_text("xesam:artist").map(|s| c_string(&s)),
+                status: Some(info.playback_status),
+                capabilities: [
+                    (info.can_go_next, MPI_CAN_GO_NEXT),
+                    (info.can_go_previous, MPI_CAN_GO_PREVIOUS),
+                    (info.can_play, MPI_CAN_PLAY),
+                    (info.can_pause, MPI_CAN_PAUSE),
+                ]
+                .into_iter()
+                .filter(|(supported, _)| *supported)
+                .fold(0, |capabilities, (_, flag)| capabilities | flag),
+                ..Default::default()
+            },
+            Some(Err(e)) => Event {
+                error: Some(c_string(&e.to_string())),
+                transient: e.is_transient(),
+                ..Default::default()
+            },
+            None => Event::default(),
+        }
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(e: impl std::fmt::Display) {
+    LAST_ERROR.with(|error| *error.borrow_mut() = Some(c_string(&e.to_string())));
+}
+
+/// Runs `f`, returning `error` instead of unwinding into the caller if it panics.
+fn guard<T>(error: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown panic");
+        set_error(format_args!("internal error: {message}"));
+        error
+    })
+}
+
+/// Message of the last error of [`mpi_open`] or [`mpi_control`] on this thread, `NULL` if
+/// there was none. The string is valid until the next failing call on this thread.
+#[no_mangle]
+pub extern "C" fn mpi_last_error() -> *const c_char {
+    LAST_ERROR.with(|error| {
+        error
+            .borrow()
+            .as_ref()
+            .map_or(ptr::null(), |error| error.as_ptr())
+    })
+}
+
+#[derive(Default)]
+struct Latest {
+    event: Option<Box<Event>>,
+    /// The player of the latest event that had one, for [`mpi_control`].
+    player: Option<String>,
+    ended: bool,
+}
+
+/// State shared with the thread following the player.
+struct Shared {
+    latest: Mutex<Latest>,
+    changed: Condvar,
+    /// Holds a byte while an event is pending or the stream ended.
+    notify: UnixStream,
+}
+
+impl Shared {
+    fn update(&self, update: impl FnOnce(&mut Latest)) {
+        let mut latest = self.latest.lock().unwrap();
+        let pending = latest.event.is_some() || latest.ended;
+        update(&mut latest);
+        if !pending && (latest.event.is_some() || latest.ended) {
+            // the socket buffer easily holds one byte, so this never blocks
+            let _ = (&self.notify).write_all(&[0]);
+        }
+        self.changed.notify_all();
+    }
+}
+
+#[derive(Default)]
+struct CloseState {
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+/// Resolves once [`mpi_close`] is called.
+#[derive(Clone, Default)]
+struct Close(Arc<Mutex<CloseState>>);
+
+impl Close {
+    fn close(&self) {
+        let mut state = self.0.lock().unwrap();
+        state.closed = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Future for Close {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.0.lock().unwrap();
+        if state.closed {
+            Poll::Ready(())
+        } else {
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+/// Connection to the session bus following the hidden active player info.
+pub struct Handle {
+    conn: Connection,
+    shared: Arc<Shared>,
+    /// Read end of [`Shared::notify`].
+    fd: UnixStream,
+    close: Close,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.close.close();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn open(default: bool) -> crate::Result<Handle> {
+    // made on the runtime of the blocking API, which keeps running its tasks
+    let conn = Connection::from(block_on(zbus::Connection::session())?);
+    let (fd, notify) = UnixStream::pair().map_err(zbus::Error::from)?;
+    let shared = Arc::new(Shared {
+        latest: Mutex::default(),
+        changed: Condvar::new(),
+        notify,
+    });
+    let close = Close::default();
+    let (started, start) = std::sync::mpsc::channel();
+    let thread = {
+        let (conn, shared, close) = (conn.clone(), shared.clone(), close.clone());
+        // the nested stream futures need more than the default 2 MiB in debug builds
+        let thread = thread::Builder::new()
+            .name("mpris-player-info".to_string())
+            .stack_size(8 << 20);
+        thread.spawn(move || {
+            let events = shared.clone();
+            // the stream has to be polled and dropped on the runtime of the blocking API
+            let follow = AssertUnwindSafe(move || {
+                block_on(async move {
+                    let stream =
+                        hidden_active_player_info_with(conn.inner(), default, AutoHide::default())
+                            .await;
+                    let stream = match stream {
+                        Ok(stream) => {
+                            let _ = started.send(Ok(()));
+                            stream
+                        }
+                        Err(e) => {
+                            let _ = started.send(Err(e));
+                            return;
+                        }
+                    };
+                    let mut stream = pin!(stream);
+                    let mut close = close;
+                    while let Either::Left((Some(info), _)) =
+                        select(stream.next(), &mut close).await
+                    {
+                        let event = Event::new(info);
+                        events.update(|latest| {
+                            if let Some(player) = &event.player {
+                                latest.player = Some(player.to_string_lossy().into_owned());
+                            }
+                            latest.event = Some(Box::new(event));
+                        });
+                    }
+                })
+            });
+            // waiting consumers are woken up even if following the player panicked
+            let _ = panic::catch_unwind(follow);
+            shared.update(|latest| latest.ended = true);
+        })
+    }
+    .map_err(zbus::Error::from)?;
+    let handle = Handle {
+        conn,
+        shared,
+        fd,
+        close,
+        thread: Some(thread),
+    };
+    // the sender is dropped without a message if the thread panicked
+    start.recv().map_err(|_| {
+        zbus::Error::Failure("following the player ended before it started".to_string())
+    })??;
+    Ok(handle)
+}
+
+/// Starts following the active player that is not hidden through the hide server. `hidden`
+/// is the hidden state used while the hide server is not running.
+///
+/// Returns `NULL` on errors, see [`mpi_last_error`].
+#[no_mangle]
+pub extern "C" fn mpi_open(hidden: bool) -> *mut Handle {
+    guard(ptr::null_mut(), || match open(hidden) {
+        Ok(handle) => Box::into_raw(Box::new(handle)),
+        Err(e) => {
+            set_error(e);
+            ptr::null_mut()
+        }
+    })
+}
+
+/// Stops following the player and frees `handle`.
+///
+/// # Safety
+///
+/// `handle` must be `NULL` or returned by [`mpi_open`] and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn mpi_close(handle: *mut Handle) {
+    if !handle.is_null() {
+        guard((), || drop(Box::from_raw(handle)));
+    }
+}
+
+/// File descriptor that is readable while [`mpi_next_event`] has an event, for use with
+/// `poll` and friends. It must not be read from or closed. It stays readable once following
+/// the player failed for good, [`mpi_next_event`] then returns `NULL`.
+///
+/// # Safety
+///
+/// `handle` must have been returned by [`mpi_open`].
+#[no_mangle]
+pub unsafe extern "C" fn mpi_fd(handle: *const Handle) -> c_int {
+    let fd: RawFd = (*handle).fd.as_raw_fd();
+    fd
+}
+
+/// The latest event, or `NULL` if there is none. With `wait` set, blocks until there is an
+/// event, `NULL` then means that following the player failed for good.
+///
+/// # Safety
+///
+/// `handle` must have been returned by [`mpi_open`].
+#[no_mangle]
+pub unsafe extern "C" fn mpi_next_event(handle: *mut Handle, wait: bool) -> *mut Event {
+    let handle = &*handle;
+    guard(ptr::null_mut(), || {
+        let mut latest = handle.shared.latest.lock().unwrap();
+        while wait && latest.event.is_none() && !latest.ended {
+            latest = handle.shared.changed.wait(latest).unwrap();
+        }
+        match latest.event.take() {
+            Some(event) => {
+                if !latest.ended {
+                    let _ = (&handle.fd).read_exact(&mut [0]);
+                }
+                Box::into_raw(event)
+            }
+            None => ptr::null_mut(),
+        }
+    })
+}
+
+/// Frees `event`.
+///
+/// # Safety
+///
+/// `event` must be `NULL` or returned by [`mpi_next_event`] and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn mpi_event_free(event: *mut Event) {
+    if !event.is_null() {
+        guard((), || drop(Box::from_raw(event)));
+    }
+}
+
+fn c_str(s: &Option<CString>) -> *const c_char {
+    s.as_deref().map_or(ptr::null(), CStr::as_ptr)
+}
+
+/// Wether the output is hidden.
+///
+/// # Safety
+///
+/// `event` must have been returned by [`mpi_next_event`].
+#[no_mangle]
+pub unsafe extern "C" fn mpi_event_hidden(event: *const Event) -> bool {
+    (*event).hidden
+}
+
+/// Bus name of the player, `NULL` if there is none. Valid until the event is freed.
+///
+/// # Safety
+///
+/// `event` must have been returned by [`mpi_next_event`].
+#[no_mangle]
+pub unsafe extern "C" fn mpi_event_player(event: *const Event) -> *const c_char {
+    c_str(&(*event).player)
+}
+
+/// Title of the track, `NULL` if unknown. Valid until the event is freed.
+///
+/// # Safety
+///
+/// `event` must have been returned by [`mpi_next_event`].
+#[no_mangle]
+pub unsafe extern "C" fn mpi_event_title(event: *const Event) -> *const c_char {
+    c_str(&(*event).title)
+}
+
+/// Artists of the track separated by commas, `NULL` if unknown. Valid until the event is
+/// freed.
+///
+/// # Safety
+///
+/// `event` must have been returned by [`mpi_next_event`].
+#[no_mangle]
+pub unsafe extern "C" fn mpi_event_artist(event: *const Event) -> *const c_char {
+    c_str(&(*event).artist)
+}
+
+/// Playback status of the player.
+///
+/// # Safety
+///
+/// `event` must have been returned by [`mpi_next_event`].
+#[no_mangle]
+pub unsafe extern "C" fn mpi_event_status(event: *const Event) -> Status {
+    match (*event).status {
+        None => Status::None,
+        Some(PlaybackStatus::Stopped) => Status::Stopped,
+        Some(PlaybackStatus::Paused) => Status::Paused,
+        Some(PlaybackStatus::Playing) => Status::Playing,
+    }
+}
+
+/// The `MPI_CAN_*` flags supported by the player.
+///
+/// # Safety
+///
+/// `event` must have been returned by [`mpi_next_event`].
+#[no_mangle]
+pub unsafe extern "C" fn mpi_event_capabilities(event: *const Event) -> u32 {
+    (*event).capabilities
+}
+
+/// Error message, `NULL` if the event is no error. Valid until the event is freed.
+///
+/// # Safety
+///
+/// `event` must have been returned by [`mpi_next_event`].
+#[no_mangle]
+pub unsafe extern "C" fn mpi_event_error(event: *const Event) -> *const c_char {
+    c_str(&(*event).error)
+}
+
+/// Wether the error might go away on its own, e.g. because a player reappears.
+///
+/// # Safety
+///
+/// `event` must have been returned by [`mpi_next_event`].
+#[no_mangle]
+pub unsafe extern "C" fn mpi_event_transient(event: *const Event) -> bool {
+    (*event).transient
+}
+
+/// Returns `false` if there is no player to control.
+fn control(handle: &Handle, action: Action) -> crate::Result<bool> {
+    let player = handle.shared.latest.lock().unwrap().player.clone();
+    let Some(player) = player else {
+        return Ok(false);
+    };
+    let proxy = PlayerProxyBlocking::builder(&handle.conn)
+        .destination(player)?
+        .cache_properties(CacheProperties::No)
+        .build()?;
+    match action {
+        Action::PlayPause => proxy.play_pause(),
+        Action::Play => proxy.play(),
+        Action::Pause => proxy.pause(),
+        Action::Stop => proxy.stop(),
+        Action::Next => proxy.next(),
+        Action::Previous => proxy.previous(),
+    }?;
+    Ok(true)
+}
+
+/// Sends `action` to the player of the latest event that had one. Returns 0 on success
+/// and -1 on errors, see [`mpi_last_error`].
+///
+/// # Safety
+///
+/// `handle` must have been returned by [`mpi_open`].
+#[no_mangle]
+pub unsafe extern "C" fn mpi_control(handle: *const Handle, action: Action) -> c_int {
+    let handle = &*handle;
+    guard(-1, || match control(handle, action) {
+        Ok(true) => 0,
+        Ok(false) => {
+            set_error("there is no player to control");
+            -1
+        }
+        Err(e) => {
+            set_error(e);
+            -1
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{ffi::CStr, sync::Arc};
+
+    use zbus::zvariant::Value;
+
+    use super::{
+        guard, mpi_event_artist, mpi_event_capabilities, mpi_event_status, mpi_last_error, Event,
+        Status, MPI_CAN_GO_NEXT, MPI_CAN_PAUSE,
+    };
+    use crate::{player_info::PlayerInfo, proxies::PlaybackStatus, test_util::player_info};
+
+    #[test]
+    fn event() {
+        let event = Event::new(Some(Some(Ok((
+            Arc::new(vec!["org.mpris.MediaPlayer2.test".to_string()]),
+            PlayerInfo {
+                can_go_next: true,
+                can_pause: true,
+                ..player_info(
+                    PlaybackStatus::Paused,
+                    &[("xesam:artist", Value::from(vec!["A", "B"]))],
+                )
+            },
+        )))));
+        unsafe {
+            assert_eq!(
+                Ok("A, B"),
+                CStr::from_ptr(mpi_event_artist(&event)).to_str()
+            );
+            assert_eq!(Status::Paused, mpi_event_status(&event));
+            assert_eq!(
+                MPI_CAN_GO_NEXT | MPI_CAN_PAUSE,
+                mpi_event_capabilities(&event)
+            );
+        }
+        let hidden = Event::new(None);
+        assert!(hidden.hidden);
+        assert_eq!(Status::None, unsafe { mpi_event_status(&hidden) });
+    }
+
+    #[test]
+    fn panic() {
+        assert_eq!(-1, guard(-1, || panic!("broken")));
+        assert_eq!(
+            Ok("internal error: broken"),
+            unsafe { CStr::from_ptr(mpi_last_error()) }.to_str()
+        );
+        assert_eq!(1, guard(-1, || 1));
+    }
+}
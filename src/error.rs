@@ -0,0 +1,179 @@
+use std::{fmt, sync::Arc};
+
+use zbus::fdo;
+
+/// Names of the errors players answer with for properties they do not implement.
+const UNSUPPORTED: [&str; 3] = [
+    "org.freedesktop.DBus.Error.NotSupported",
+    "org.freedesktop.DBus.Error.UnknownProperty",
+    "org.freedesktop.DBus.Error.InvalidArgs",
+];
+
+/// Errors reported by the streams of this crate.
+///
+/// Errors are cheap to clone, so they can be passed along with the stream items.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// playerctld is not on the bus.
+    PlayerctldNotRunning,
+    /// The followed player left the bus.
+    PlayerVanished(String),
+    /// The hide server is not on the bus.
+    HideServerMissing,
+    /// The player does not implement a property.
+    PropertyUnsupported {
+        property: &'static str,
+        source: Arc<zbus::Error>,
+    },
+    /// The player sent metadata that is not a dictionary of variants.
+    MalformedMetadata(Arc<zbus::Error>),
+    /// Any other dbus error.
+    DBus(Arc<zbus::Error>),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Wether `e` tells that a property is not implemented, as opposed to failures of the
+/// connection or the player.
+fn is_unsupported(e: &zbus::Error) -> bool {
+    match e {
+        zbus::Error::FDO(e) => matches!(
+            e.as_ref(),
+            fdo::Error::NotSupported(_)
+                | fdo::Error::UnknownProperty(_)
+                | fdo::Error::InvalidArgs(_)
+        ),
+        zbus::Error::MethodError(name, _, _) => UNSUPPORTED.contains(&name.as_str()),
+        _ => false,
+    }
+}
+
+impl Error {
+    /// Classifies the failure to read `property`. Only answers that the property is not
+    /// implemented make it unsupported, everything else is passed on as [`Error::DBus`].
+    pub fn property(property: &'static str, source: zbus::Error) -> Self {
+        match source {
+            zbus::Error::Variant(_) if property == "Metadata" => {
+                Error::MalformedMetadata(Arc::new(source))
+            }
+            source if is_unsupported(&source) => Error::PropertyUnsupported {
+                property,
+                source: Arc::new(source),
+            },
+            source => Error::DBus(Arc::new(source)),
+        }
+    }
+
+    /// Wether the cause might go away on its own, e.g. because a missing service reappears.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::PlayerctldNotRunning | Error::PlayerVanished(_) | Error::HideServerMissing => {
+                true
+            }
+            Error::PropertyUnsupported { .. } | Error::MalformedMetadata(_) => false,
+            Error::DBus(e) => matches!(
+                e.as_ref(),
+                zbus::Error::InputOutput(_) | zbus::Error::MethodError(..) | zbus::Error::FDO(_)
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::PlayerctldNotRunning => write!(f, "playerctld is not running"),
+            Error::PlayerVanished(name) => write!(f, "player {name} vanished"),
+            Error::HideServerMissing => write!(f, "hide server is not running"),
+            Error::PropertyUnsupported { property, .. } => {
+                write!(f, "player does not support {property}")
+            }
+            Error::MalformedMetadata(_) => write!(f, "player sent malformed metadata"),
+            Error::DBus(e) => write!(f, "dbus error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::PlayerctldNotRunning | Error::PlayerVanished(_) | Error::HideServerMissing => {
+                None
+            }
+            Error::PropertyUnsupported { source, .. }
+            | Error::MalformedMetadata(source)
+            | Error::DBus(source) => Some(source.as_ref()),
+        }
+    }
+}
+
+impl From<zbus::Error> for Error {
+    fn from(e: zbus::Error) -> Self {
+        Error::DBus(Arc::new(e))
+    }
+}
+
+impl From<zbus::fdo::Error> for Error {
+    fn from(e: zbus::fdo::Error) -> Self {
+        Error::DBus(Arc::new(e.into()))
+    }
+}
+
+impl From<zbus::names::Error> for Error {
+    fn from(e: zbus::names::Error) -> Self {
+        Error::DBus(Arc::new(e.into()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use zbus::fdo;
+
+    use super::Error;
+
+    #[test]
+    fn property_errors() {
+        let variant = zbus::Error::Variant(zbus::zvariant::Error::IncorrectType);
+        assert!(matches!(
+            Error::property("Metadata", variant.clone()),
+            Error::MalformedMetadata(_)
+        ));
+        assert!(matches!(
+            Error::property("CanPlay", variant),
+            Error::DBus(_)
+        ));
+        let not_supported = || fdo::Error::NotSupported("CanPlay".to_string()).into();
+        assert!(matches!(
+            Error::property("CanPlay", not_supported()),
+            Error::PropertyUnsupported {
+                property: "CanPlay",
+                ..
+            }
+        ));
+        assert!(matches!(
+            Error::property(
+                "Shuffle",
+                fdo::Error::UnknownProperty("Shuffle".to_string()).into()
+            ),
+            Error::PropertyUnsupported { .. }
+        ));
+        assert!(!Error::property("CanPlay", not_supported()).is_transient());
+        assert_eq!(
+            "player does not support CanPlay",
+            Error::property("CanPlay", not_supported()).to_string()
+        );
+        // a vanished player or a broken connection is retried
+        let vanished = Error::property(
+            "CanPlay",
+            fdo::Error::ServiceUnknown("org.mpris.MediaPlayer2.mpv".to_string()).into(),
+        );
+        assert!(matches!(vanished, Error::DBus(_)));
+        assert!(vanished.is_transient());
+        let io = Error::property(
+            "CanPlay",
+            std::io::Error::from(std::io::ErrorKind::BrokenPipe).into(),
+        );
+        assert!(io.is_transient());
+        assert!(Error::PlayerVanished("org.mpris.MediaPlayer2.mpv".to_string()).is_transient());
+    }
+}
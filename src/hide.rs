@@ -4,28 +4,74 @@ use std::sync::Arc;
 use tracing::{debug_span, event, info, Instrument, Level};
 use zbus::{
     export::futures_util::{Stream, StreamExt},
+    names::BusName,
     Connection,
 };
 
 use crate::{
     proxies::HideStateProxy,
-    util::{poll_both, ResultExt, StreamExt2},
+    stream::{combine_latest, StreamExt2, Termination},
+    util::{follow_owner, ResultExt},
+    Error,
 };
 
 #[cfg(feature = "hide_active_player_info")]
-use crate::{active_player_info::active_player_info, player_info::PlayerInfo};
+use crate::{
+    active_player::active_players,
+    active_player_info::follow_first_player,
+    player_info::{PlayerInfo, COALESCE_WINDOW},
+};
+#[cfg(feature = "hide_active_player_info")]
+use crate::proxies::PlaybackStatus;
+#[cfg(feature = "hide_active_player_info")]
+use std::{
+    future::ready,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+#[cfg(feature = "hide_active_player_info")]
+use crate::util::{sleep, Sleep};
+
+use std::{fmt::Debug, future::Future};
+
+const HIDE_SERVER: &str = "com.github.robinmarchart.mprisutils";
 
-use std::fmt::Debug;
+/// Follows the hide server, activating it if possible. While the server is not available
+/// an error is reported, so callers fall back to their defaults until it appears.
+async fn follow_hide_server<F, Fut, S, T>(
+    conn: &Connection,
+    connect: F,
+) -> crate::Result<impl Stream<Item = crate::Result<T>> + 'static>
+where
+    F: FnMut() -> Fut + 'static,
+    Fut: Future<Output = crate::Result<S>> + 'static,
+    S: Stream<Item = crate::Result<T>> + 'static,
+    T: 'static,
+{
+    let name = BusName::from_static_str(HIDE_SERVER)?;
+    follow_owner(conn, name, true, Error::HideServerMissing, connect).await
+}
 
-pub async fn hidden(conn: &Connection) -> zbus::Result<impl Stream<Item = zbus::Result<bool>>> {
+pub async fn hidden(
+    conn: &Connection,
+) -> crate::Result<impl Stream<Item = crate::Result<bool>> + 'static> {
     let span = debug_span!("hidden");
     async {
-        let proxy = HideStateProxy::new(conn).await?;
-        let stream = proxy.receive_hidden_changed().await;
-        info!("connected to hide state server");
-        Ok(stream
-            .then(|event| async move { event.get().await })
-            .with_initial_value(proxy.hidden().await).filter_no_change())
+        let owned = conn.clone();
+        let stream = follow_hide_server(conn, move || {
+            let conn = owned.clone();
+            async move {
+                let proxy = HideStateProxy::new(&conn).await?;
+                let stream = proxy.receive_hidden_changed().await;
+                info!("connected to hide state server");
+                Ok(stream
+                    .then(|event| async move { Ok(event.get().await?) })
+                    .with_initial_value(proxy.hidden().await.map_err(Error::from)))
+            }
+        })
+        .await?;
+        Ok(stream.filter_no_change())
     }
     .instrument(span.clone())
     .await
@@ -36,21 +82,140 @@ pub async fn hidden(conn: &Connection) -> zbus::Result<impl Stream<Item = zbus::
     })
 }
 
-pub async fn hide<S: Stream>(
+/// Stream of the players hidden through the hide server.
+pub async fn hidden_players(
+    conn: &Connection,
+) -> crate::Result<impl Stream<Item = crate::Result<Vec<String>>> + 'static> {
+    let span = debug_span!("hidden_players");
+    async {
+        let owned = conn.clone();
+        let stream = follow_hide_server(conn, move || {
+            let conn = owned.clone();
+            async move {
+                let proxy = HideStateProxy::new(&conn).await?;
+                let stream = proxy.receive_hidden_players_changed().await;
+                info!("connected to hide state server");
+                Ok(stream
+                    .then(|event| async move { Ok(event.get().await?) })
+                    .with_initial_value(proxy.hidden_players().await.map_err(Error::from)))
+            }
+        })
+        .await?;
+        Ok(stream.filter_no_change())
+    }
+    .instrument(span.clone())
+    .await
+    .trace_err_span(&span)
+    .map(|s| {
+        s.inspect(|r| event!(Level::DEBUG,hidden_players = ?r))
+            .instrument_stream(span)
+    })
+}
+
+/// Wether the mpris bus name `bus_name` refers to the hidden player `player`.
+///
+/// `player` is either the full bus name or the part after `org.mpris.MediaPlayer2.`,
+/// which also matches all instances of that player.
+pub fn player_matches(bus_name: &str, player: &str) -> bool {
+    if bus_name == player {
+        return true;
+    }
+    match bus_name
+        .strip_prefix("org.mpris.MediaPlayer2.")
+        .and_then(|name| name.strip_prefix(player))
+    {
+        Some(rest) => rest.is_empty() || rest.starts_with('.'),
+        None => false,
+    }
+}
+
+/// Stream of a named flag stored by the hide server, starting with its current value.
+pub async fn flag(
+    conn: &Connection,
+    name: &str,
+) -> crate::Result<impl Stream<Item = crate::Result<bool>> + 'static> {
+    let span = debug_span!("flag", name);
+    async {
+        let owned = conn.clone();
+        let name = name.to_owned();
+        let stream = follow_hide_server(conn, move || {
+            let conn = owned.clone();
+            let name = name.clone();
+            async move {
+                let proxy = HideStateProxy::new(&conn).await?;
+                let stream = proxy.receive_flag_changed_with_args(&[(0, name.as_str())]).await?;
+                info!("connected to hide state server");
+                Ok(stream
+                    .map(|signal| Ok(signal.args().map(|args| args.value).trace_err()?))
+                    .with_initial_value(proxy.get_flag(&name).await.map_err(Error::from)))
+            }
+        })
+        .await?;
+        Ok(stream.filter_no_change())
+    }
+    .instrument(span.clone())
+    .await
+    .trace_err_span(&span)
+    .map(|s| {
+        s.inspect(|r| event!(Level::DEBUG,flag = ?r))
+            .instrument_stream(span)
+    })
+}
+
+/// Stream of a named value stored by the hide server, starting with its current value.
+/// Unset values are reported as `None`.
+pub async fn value(
+    conn: &Connection,
+    name: &str,
+) -> crate::Result<impl Stream<Item = crate::Result<Option<String>>> + 'static> {
+    let span = debug_span!("value", name);
+    async {
+        let owned = conn.clone();
+        let name = name.to_owned();
+        let stream = follow_hide_server(conn, move || {
+            let conn = owned.clone();
+            let name = name.clone();
+            async move {
+                let proxy = HideStateProxy::new(&conn).await?;
+                let stream = proxy.receive_value_changed_with_args(&[(0, name.as_str())]).await?;
+                info!("connected to hide state server");
+                Ok(stream
+                    .map(|signal| {
+                        Ok(signal
+                            .args()
+                            .map(|args| args.value.to_string())
+                            .trace_err()?)
+                    })
+                    .with_initial_value(proxy.get_value(&name).await.map_err(Error::from)))
+            }
+        })
+        .await?;
+        Ok(stream
+            .map(|value| value.map(|value| Some(value).filter(|value| !value.is_empty())))
+            .filter_no_change())
+    }
+    .instrument(span.clone())
+    .await
+    .trace_err_span(&span)
+    .map(|s| {
+        s.inspect(|r| event!(Level::DEBUG,value = ?r))
+            .instrument_stream(span)
+    })
+}
+
+pub async fn hide<S: Stream + 'static>(
     conn: &Connection,
     s: S,
     default: bool,
-) -> zbus::Result<impl Stream<Item = Option<S::Item>>>
+) -> crate::Result<impl Stream<Item = Option<S::Item>> + 'static>
 where
     S::Item: Clone + Debug,
 {
     let span = debug_span!("hidden");
     async move {
-        Ok(poll_both(
-            hidden(conn)
-                .await?
-                .map(move |v| v.unwrap_or(default)),
-            s,
+        Ok(combine_latest(
+            (hidden(conn).await?.map(move |v| v.unwrap_or(default)), s),
+            Termination::Any,
         )
         .map(move |(hidden, val)| {
             let hidden = hidden.unwrap_or(default);
@@ -70,19 +235,219 @@ where
     })
 }
 
+/// Rules for hiding the player output automatically.
+///
+/// A toggle of the hidden state through the hide server inverts what is currently shown and
+/// overrides the rules until the playback status, the presence of a title or the followed
+/// player changes. A hidden state of the hide server at start hides the output until then as
+/// well. Without any rules the hidden state from the hide server is used as is.
+#[cfg(feature = "hide_active_player_info")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AutoHide {
+    /// Hide once the player has been paused or stopped for this long.
+    pub idle: Option<Duration>,
+    /// Hide while the metadata contains no title.
+    pub untitled: bool,
+}
+
+#[cfg(feature = "hide_active_player_info")]
+impl AutoHide {
+    pub fn is_enabled(&self) -> bool {
+        self.idle.is_some() || self.untitled
+    }
+}
+
+/// The active players and the info of the first one, `None` if there is no player.
+#[cfg(feature = "hide_active_player_info")]
+pub type HiddenInfo = Option<crate::Result<(Arc<Vec<String>>, PlayerInfo)>>;
+
+/// The parts of the player state whose change ends a manual override.
+#[cfg(feature = "hide_active_player_info")]
+#[derive(Debug, Clone, PartialEq)]
+struct AutoHideKey {
+    player: String,
+    playback_status: PlaybackStatus,
+    titled: bool,
+}
+
+#[cfg(feature = "hide_active_player_info")]
+impl AutoHideKey {
+    fn new(info: &HiddenInfo) -> Option<Self> {
+        match info {
+            Some(Ok((names, info))) => Some(Self {
+                player: names.first().cloned().unwrap_or_default(),
+                playback_status: info.playback_status,
+                titled: info
+                    .metadata
+                    .get("xesam:title")
+                    .and_then(|title| title.downcast_ref::<str>())
+                    .is_some_and(|title| !title.is_empty()),
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "hide_active_player_info")]
+struct AutoHideStream<M, S> {
+    manual: Pin<Box<M>>,
+    info: Pin<Box<S>>,
+    rules: AutoHide,
+    manual_override: Option<bool>,
+    /// The last hidden state of the hide server, `None` before the first one.
+    manual_state: Option<bool>,
+    current: Option<HiddenInfo>,
+    key: Option<AutoHideKey>,
+    timer: Option<Pin<Box<Sleep>>>,
+    idle: bool,
+}
+
+#[cfg(feature = "hide_active_player_info")]
+impl<M, S> AutoHideStream<M, S> {
+    fn hidden(&self) -> bool {
+        self.manual_override.unwrap_or_else(|| {
+            self.idle
+                || (self.rules.untitled && self.key.as_ref().is_some_and(|key| !key.titled))
+        })
+    }
+}
+
+#[cfg(feature = "hide_active_player_info")]
+impl<M, S> Stream for AutoHideStream<M, S>
+where
+    M: Stream<Item = bool>,
+    S: Stream<Item = HiddenInfo>,
+{
+    type Item = Option<HiddenInfo>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let mut changed = false;
+        loop {
+            match this.manual.as_mut().poll_next(cx) {
+                Poll::Ready(Some(hidden)) => {
+                    match this.manual_state.replace(hidden) {
+                        // the state at start, a persisted hidden state is kept
+                        None => this.manual_override = hidden.then_some(true),
+                        Some(last) if last != hidden => {
+                            // the flag of the server may be stale after an override expired,
+                            // so any change of it toggles what is shown
+                            this.manual_override = Some(!this.hidden());
+                        }
+                        Some(_) => continue,
+                    }
+                    info!("manual override: hidden = {:?}", this.manual_override);
+                    changed = true;
+                }
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => break,
+            }
+        }
+        loop {
+            match this.info.as_mut().poll_next(cx) {
+                Poll::Ready(Some(info)) => {
+                    let key = AutoHideKey::new(&info);
+                    if key != this.key {
+                        // the first info keeps the state at start
+                        if this.current.is_some() {
+                            this.manual_override = None;
+                        }
+                        this.idle = false;
+                        this.timer = match (&key, this.rules.idle) {
+                            (Some(key), Some(idle))
+                                if key.playback_status != PlaybackStatus::Playing =>
+                            {
+                                Some(Box::pin(sleep(idle)))
+                            }
+                            _ => None,
+                        };
+                        this.key = key;
+                    }
+                    this.current = Some(info);
+                    changed = true;
+                }
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => break,
+            }
+        }
+        if let Some(timer) = this.timer.as_mut() {
+            if timer.as_mut().poll(cx).is_ready() {
+                info!("player idle");
+                this.timer = None;
+                this.idle = true;
+                this.manual_override = None;
+                changed = true;
+            }
+        }
+        match &this.current {
+            Some(info) if changed => Poll::Ready(Some(if this.hidden() {
+                None
+            } else {
+                Some(info.clone())
+            })),
+            _ => Poll::Pending,
+        }
+    }
+}
+
+/// Info about the first player not hidden through the hide server, or `None` if everything is hidden.
 #[cfg(feature = "hide_active_player_info")]
 pub async fn hidden_active_player_info(
     conn: &Connection,
     default: bool,
-) -> zbus::Result<
-    impl Stream<Item = Option<Option<Result<(Arc<Vec<String>>, PlayerInfo), Arc<zbus::Error>>>>>,
-> {
-    let span = debug_span!("hide_active_player_info");
+) -> crate::Result<impl Stream<Item = Option<HiddenInfo>> + 'static> {
+    hidden_active_player_info_with(conn, default, AutoHide::default()).await
+}
+
+/// Like [`hidden_active_player_info`], but additionally hides the output according to `rules`.
+#[cfg(feature = "hide_active_player_info")]
+pub async fn hidden_active_player_info_with(
+    conn: &Connection,
+    default: bool,
+    rules: AutoHide,
+) -> crate::Result<impl Stream<Item = Option<HiddenInfo>> + 'static> {
+    let span = debug_span!("hide_active_player_info", ?rules);
     async move {
-        let info = active_player_info(conn.clone())
+        let hidden_players = hidden_players(conn)
             .await?
-            .map(|v| v.map(|v| v.map_err(Arc::new)));
-        hide(conn, info, default).await
+            .map(|players| players.unwrap_or_default());
+        let names = combine_latest(
+            (active_players(conn).await?, hidden_players),
+            Termination::Any,
+        )
+        .filter_map(|(names, hidden_players)| {
+            ready(names.map(|names| {
+                names.map(|names| {
+                    names
+                        .into_iter()
+                        .filter(|name| {
+                            !hidden_players
+                                .iter()
+                                .flatten()
+                                .any(|player| player_matches(name, player))
+                        })
+                        .collect::<Vec<_>>()
+                })
+            }))
+        })
+        .filter_no_change();
+        let info = follow_first_player(conn.clone(), names, COALESCE_WINDOW);
+        Ok(if rules.is_enabled() {
+            AutoHideStream {
+                manual: Box::pin(hidden(conn).await?.map(move |v| v.unwrap_or(default))),
+                info: Box::pin(info),
+                rules,
+                manual_override: None,
+                manual_state: None,
+                current: None,
+                key: None,
+                timer: None,
+                idle: false,
+            }
+            .left_stream()
+        } else {
+            hide(conn, info, default).await?.right_stream()
+        })
     }
     .instrument(span.clone())
     .await
@@ -92,3 +457,155 @@ pub async fn hidden_active_player_info(
             .instrument_stream(span)
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::player_matches;
+
+    #[cfg(all(feature = "hide_active_player_info", feature = "tokio"))]
+    mod auto_hide {
+        use std::{pin::pin, sync::Arc, time::Duration};
+
+        use tokio::sync::mpsc;
+        use tokio_stream::wrappers::ReceiverStream;
+        use zbus::{export::futures_util::StreamExt, zvariant::Value};
+
+        use crate::{
+            hide::{AutoHide, AutoHideStream, HiddenInfo},
+            proxies::PlaybackStatus,
+            test_util::player_info,
+        };
+
+        fn info(playback_status: PlaybackStatus, title: Option<&str>) -> HiddenInfo {
+            let metadata: Vec<_> = title
+                .map(|title| ("xesam:title", Value::from(title)))
+                .into_iter()
+                .collect();
+            Some(Ok((
+                Arc::new(vec!["org.mpris.MediaPlayer2.test".to_string()]),
+                player_info(playback_status, &metadata),
+            )))
+        }
+
+        type Channels = (mpsc::Sender<bool>, mpsc::Sender<HiddenInfo>);
+
+        fn stream(
+            rules: AutoHide,
+        ) -> (
+            Channels,
+            AutoHideStream<ReceiverStream<bool>, ReceiverStream<HiddenInfo>>,
+        ) {
+            let (manual_in, manual) = mpsc::channel(4);
+            let (info_in, info_stream) = mpsc::channel(4);
+            let stream = AutoHideStream {
+                manual: Box::pin(ReceiverStream::new(manual)),
+                info: Box::pin(ReceiverStream::new(info_stream)),
+                rules,
+                manual_override: None,
+                manual_state: None,
+                current: None,
+                key: None,
+                timer: None,
+                idle: false,
+            };
+            ((manual_in, info_in), stream)
+        }
+
+        #[tokio::test(start_paused = true)]
+        async fn idle_and_override() {
+            let ((manual_in, info_in), s) = stream(AutoHide {
+                idle: Some(Duration::from_secs(60)),
+                untitled: true,
+            });
+            let mut s = pin!(s);
+            manual_in.send(false).await.unwrap();
+            let playing = info(PlaybackStatus::Playing, Some("title"));
+            info_in.send(playing.clone()).await.unwrap();
+            assert_eq!(Some(Some(playing.clone())), s.next().await, "playing");
+
+            let paused = info(PlaybackStatus::Paused, Some("title"));
+            info_in.send(paused.clone()).await.unwrap();
+            assert_eq!(Some(Some(paused.clone())), s.next().await, "just paused");
+            assert_eq!(Some(None), s.next().await, "idle");
+
+            manual_in.send(true).await.unwrap();
+            assert_eq!(Some(Some(paused)), s.next().await, "manual override");
+
+            info_in.send(playing.clone()).await.unwrap();
+            assert_eq!(Some(Some(playing)), s.next().await, "playing again");
+
+            let untitled = info(PlaybackStatus::Playing, None);
+            info_in.send(untitled.clone()).await.unwrap();
+            assert_eq!(Some(None), s.next().await, "untitled");
+
+            manual_in.send(false).await.unwrap();
+            assert_eq!(Some(Some(untitled)), s.next().await, "manually shown");
+            drop(manual_in);
+            assert_eq!(None, s.next().await, "manual stream closed");
+        }
+
+        #[tokio::test(start_paused = true)]
+        async fn toggle_after_change() {
+            let ((manual_in, info_in), s) = stream(AutoHide {
+                idle: Some(Duration::from_secs(60)),
+                untitled: false,
+            });
+            let mut s = pin!(s);
+            manual_in.send(false).await.unwrap();
+            let playing = info(PlaybackStatus::Playing, Some("title"));
+            info_in.send(playing.clone()).await.unwrap();
+            assert_eq!(Some(Some(playing)), s.next().await, "playing");
+
+            manual_in.send(true).await.unwrap();
+            assert_eq!(Some(None), s.next().await, "hidden");
+
+            // the status change ends the override while the flag of the server stays set
+            let paused = info(PlaybackStatus::Paused, Some("title"));
+            info_in.send(paused.clone()).await.unwrap();
+            assert_eq!(Some(Some(paused.clone())), s.next().await, "paused");
+
+            manual_in.send(false).await.unwrap();
+            assert_eq!(Some(None), s.next().await, "hidden again");
+            manual_in.send(true).await.unwrap();
+            assert_eq!(Some(Some(paused)), s.next().await, "shown again");
+        }
+
+        #[tokio::test(start_paused = true)]
+        async fn hidden_at_start() {
+            let ((manual_in, info_in), s) = stream(AutoHide {
+                idle: None,
+                untitled: true,
+            });
+            let mut s = pin!(s);
+            manual_in.send(true).await.unwrap();
+            let playing = info(PlaybackStatus::Playing, Some("title"));
+            info_in.send(playing.clone()).await.unwrap();
+            assert_eq!(Some(None), s.next().await, "persisted hidden state");
+
+            info_in
+                .send(info(PlaybackStatus::Paused, Some("title")))
+                .await
+                .unwrap();
+            assert_eq!(
+                Some(Some(info(PlaybackStatus::Paused, Some("title")))),
+                s.next().await,
+                "status changed"
+            );
+        }
+    }
+
+    #[test]
+    fn player_matches_names() {
+        assert!(player_matches("org.mpris.MediaPlayer2.spotify", "spotify"));
+        assert!(player_matches(
+            "org.mpris.MediaPlayer2.spotify",
+            "org.mpris.MediaPlayer2.spotify"
+        ));
+        assert!(player_matches(
+            "org.mpris.MediaPlayer2.firefox.instance_1_84",
+            "firefox"
+        ));
+        assert!(!player_matches("org.mpris.MediaPlayer2.firefoxdev", "firefox"));
+        assert!(!player_matches("org.mpris.MediaPlayer2.spotify", "mpv"));
+    }
+}
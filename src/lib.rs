@@ -1,5 +1,8 @@
 pub mod proxies;
 
+mod error;
+pub use error::{Error, Result};
+
 #[cfg(feature = "playerctld")]
 pub mod active_player;
 
@@ -9,7 +12,29 @@ pub mod active_player_info;
 #[cfg(feature = "player_info")]
 pub mod player_info;
 
+#[cfg(feature = "player_info")]
+pub mod position;
+
+#[cfg(feature = "lyrics")]
+pub mod lyrics;
+
 #[cfg(feature = "hide")]
 pub mod hide;
 
+#[cfg(feature = "blocking")]
+pub mod blocking;
+
+#[cfg(feature = "python")]
+mod python;
+
+#[cfg(feature = "capi")]
+pub mod capi;
+
+#[cfg(feature = "stream")]
+pub mod stream;
+
+#[cfg(feature = "stream")]
 pub(crate) mod util;
+
+#[cfg(all(test, feature = "hide_active_player_info"))]
+mod test_util;
@@ -0,0 +1,423 @@
+//! Synchronized lyrics from `.lrc` files.
+
+use std::{
+    ffi::OsString,
+    fs, io,
+    os::unix::ffi::OsStringExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures_util::future::ready;
+use tracing::{debug_span, event, info, warn, Instrument, Level};
+use zbus::{
+    export::futures_util::{
+        stream::{self, once},
+        Stream, StreamExt,
+    },
+    names::BusName,
+    Connection,
+};
+
+use crate::{
+    active_player_info::active_player_info,
+    player_info::PlayerInfo,
+    position::{position, Position},
+    stream::StreamExt2,
+    util::{sleep, unblock, ResultExt},
+};
+
+/// A line of lyrics, shown from `time` on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub time: Duration,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Lyrics {
+    /// The lines sorted by time.
+    pub lines: Vec<Line>,
+}
+
+/// Parses `mm:ss`, `mm:ss.xx` or `mm:ss:xx`.
+fn parse_time(tag: &str) -> Option<Duration> {
+    let (minutes, rest) = tag.split_once(':')?;
+    let (seconds, fraction) = match rest.split_once(['.', ':']) {
+        Some((seconds, fraction)) => (seconds, fraction),
+        None => (rest, "0"),
+    };
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if !digits(minutes) || !digits(seconds) || !digits(fraction) {
+        return None;
+    }
+    let fraction = &fraction[..fraction.len().min(3)];
+    let millis = fraction.parse::<u64>().ok()? * 10u64.pow(3 - fraction.len() as u32);
+    Some(
+        Duration::from_secs(minutes.parse::<u64>().ok()? * 60 + seconds.parse::<u64>().ok()?)
+            + Duration::from_millis(millis),
+    )
+}
+
+/// Removes the word timestamps `<mm:ss.xx>` of enhanced lrc files.
+fn strip_word_times(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        match rest[start..].find('>') {
+            Some(end) if parse_time(&rest[start + 1..start + end]).is_some() => {
+                stripped.push_str(&rest[..start]);
+                rest = &rest[start + end + 1..];
+            }
+            _ => {
+                stripped.push_str(&rest[..=start]);
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+impl Lyrics {
+    /// Parses the content of a `.lrc` file. Lines without timestamps and unknown tags are
+    /// skipped, a line may have several timestamps and the `offset` tag is applied.
+    pub fn parse(content: &str) -> Self {
+        let mut lines = Vec::new();
+        // milliseconds the lines are shown earlier
+        let mut offset: i64 = 0;
+        for line in content.lines() {
+            let mut rest = line.trim();
+            let mut times = Vec::new();
+            while let Some((tag, after)) = rest.strip_prefix('[').and_then(|r| r.split_once(']')) {
+                if let Some(time) = parse_time(tag.trim()) {
+                    times.push(time);
+                } else if let Some(value) = tag.strip_prefix("offset:") {
+                    offset = value.trim().parse().unwrap_or(offset);
+                } else {
+                    break;
+                }
+                rest = after;
+            }
+            let text = strip_word_times(rest).trim().to_string();
+            lines.extend(times.into_iter().map(|time| Line {
+                time,
+                text: text.clone(),
+            }));
+        }
+        let shift = Duration::from_millis(offset.unsigned_abs());
+        for line in &mut lines {
+            line.time = if offset > 0 {
+                line.time.saturating_sub(shift)
+            } else {
+                line.time + shift
+            };
+        }
+        lines.sort_by_key(|line| line.time);
+        Lyrics { lines }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    /// The line shown at `position`, `None` before the first line.
+    pub fn line_at(&self, position: Duration) -> Option<&str> {
+        let index = self.lines.partition_point(|line| line.time <= position);
+        Some(&self.lines.get(index.checked_sub(1)?)?.text)
+    }
+
+    /// When the line after the one shown at `position` starts.
+    pub fn next_change(&self, position: Duration) -> Option<Duration> {
+        let index = self.lines.partition_point(|line| line.time <= position);
+        Some(self.lines.get(index)?.time)
+    }
+}
+
+/// The path of a `file://` url.
+fn file_path(url: &str) -> Option<PathBuf> {
+    let path = url.strip_prefix("file://")?;
+    let path = path.strip_prefix("localhost").unwrap_or(path);
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    Some(OsString::from_vec(decoded).into())
+}
+
+/// The track the lyrics are looked up for.
+#[derive(Debug, Clone, PartialEq)]
+struct Track {
+    player: String,
+    url: Option<String>,
+    artist: Option<String>,
+    title: Option<String>,
+}
+
+impl Track {
+    fn new(player: String, info: &PlayerInfo) -> Self {
+        Track {
+            player,
+            url: info.metadata_text("xesam:url"),
+            artist: info.metadata_text("xesam:artist"),
+            title: info.metadata_text("xesam:title"),
+        }
+    }
+
+    fn find(&self, dirs: &[PathBuf]) -> Option<PathBuf> {
+        let file = self.url.as_deref().and_then(file_path);
+        let mut candidates = Vec::new();
+        let name = file.as_ref().and_then(|file| {
+            candidates.push(file.with_extension("lrc"));
+            Some(Path::new(file.file_stem()?).with_extension("lrc"))
+        });
+        let track = match (&self.artist, &self.title) {
+            (Some(artist), Some(title)) => {
+                Some(format!("{artist} - {title}.lrc").replace('/', "_"))
+            }
+            _ => None,
+        };
+        for dir in dirs {
+            candidates.extend(name.iter().map(|name| dir.join(name)));
+            candidates.extend(track.iter().map(|track| dir.join(track)));
+        }
+        candidates.into_iter().find(|candidate| candidate.is_file())
+    }
+}
+
+/// Looks for the lyrics of the track: `<file name>.lrc` next to the file of a local
+/// `xesam:url`, then `<file name>.lrc` and `<artist> - <title>.lrc` in `dirs`.
+pub fn find(info: &PlayerInfo, dirs: &[PathBuf]) -> Option<PathBuf> {
+    Track::new(String::new(), info).find(dirs)
+}
+
+/// Yields the line at `position` and every following line until playback stops.
+fn lines(lyrics: Arc<Lyrics>, position: Position) -> impl Stream<Item = Option<String>> {
+    stream::unfold(true, move |first| {
+        let lyrics = lyrics.clone();
+        async move {
+            if !first {
+                if !position.playing || position.rate <= 0.0 {
+                    return None;
+                }
+                let now = position.extrapolate(Instant::now());
+                let next = lyrics.next_change(now)?;
+                // stops for a NaN rate, or one too small to wait for the next line
+                let wait = (next - now).as_secs_f64() / position.rate;
+                sleep(Duration::try_from_secs_f64(wait).ok()?).await;
+            }
+            let line = lyrics.line_at(position.extrapolate(Instant::now()));
+            Some((line.map(str::to_string), false))
+        }
+    })
+}
+
+/// Follows the line of `lyrics` at the positions of `positions`, extrapolating the position
+/// between them. `None` before the first line.
+pub fn current_line<S>(
+    lyrics: Arc<Lyrics>,
+    positions: S,
+) -> impl Stream<Item = crate::Result<Option<String>>>
+where
+    S: Stream<Item = crate::Result<Position>>,
+{
+    positions
+        .map(move |position| match position {
+            Ok(position) => lines(lyrics.clone(), position).map(Ok).right_stream(),
+            Err(e) => once(ready(Err(e))).left_stream(),
+        })
+        .flatten_newest()
+        .filter_no_change()
+}
+
+/// The lines of the lyrics of `track`.
+async fn follow(
+    track: Option<crate::Result<Track>>,
+    conn: Connection,
+    dirs: Vec<PathBuf>,
+) -> impl Stream<Item = crate::Result<Option<String>>> {
+    let track = match track {
+        None => return once(ready(Ok(None))).left_stream(),
+        Some(Err(e)) => return once(ready(Err(e))).left_stream(),
+        Some(Ok(track)) => track,
+    };
+    let lookup = track.clone();
+    let found = unblock(move || {
+        let path = lookup.find(&dirs)?;
+        let lyrics = Lyrics::load(&path);
+        Some((path, lyrics))
+    })
+    .await;
+    let Some((path, lyrics)) = found else {
+        info!("no lyrics for {track:?}");
+        return once(ready(Ok(None))).left_stream();
+    };
+    let lyrics = match lyrics {
+        Ok(lyrics) => lyrics,
+        Err(e) => {
+            warn!("could not read {}: {e}", path.display());
+            return once(ready(Ok(None))).left_stream();
+        }
+    };
+    info!("showing the lyrics of {}", path.display());
+    let positions = match BusName::try_from(track.player) {
+        Ok(name) => position(name, &conn).await,
+        Err(e) => Err(e.into()),
+    };
+    match positions {
+        Ok(positions) => current_line(Arc::new(lyrics), positions).right_stream(),
+        Err(e) => once(ready(Err(e))).left_stream(),
+    }
+}
+
+/// Follows the current line of the lyrics of the active player, looked up with [`find`].
+/// Yields `None` while there is no line to show, e.g. without lyrics.
+pub async fn active_lyrics(
+    conn: Connection,
+    dirs: Vec<PathBuf>,
+) -> crate::Result<impl Stream<Item = crate::Result<Option<String>>> + 'static> {
+    let span = debug_span!("active_lyrics");
+    async move {
+        let info = active_player_info(conn.clone()).await?;
+        Ok(info
+            .map(|info| {
+                info.map(|info| {
+                    info.map(|(players, info)| {
+                        Track::new(players.first().cloned().unwrap_or_default(), &info)
+                    })
+                })
+            })
+            .filter_no_change()
+            .map(move |track| once(follow(track, conn.clone(), dirs.clone())).flatten())
+            .flatten_newest()
+            .filter_no_change())
+    }
+    .instrument(span.clone())
+    .await
+    .trace_err_span(&span)
+    .map(|s| {
+        s.inspect(|r| event!(Level::DEBUG, lyrics = ?r))
+            .instrument_stream(span)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        env, fs,
+        path::PathBuf,
+        process,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use tokio::time::timeout;
+    use tokio_stream::StreamExt;
+    use zbus::export::futures_util::stream;
+
+    use super::{current_line, file_path, Line, Lyrics, Track};
+    use crate::position::Position;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn parse() {
+        let lyrics = Lyrics::parse(
+            "[ar:Artist]\n\
+             [offset:+100]\n\
+             [00:01.50]first\n\
+             [00:03.00][00:10.2]<00:03.00>re<00:03.50>peated\n\
+             no timestamp\n\
+             [00:05]\n\
+             [01:02:345] [verse]\n",
+        );
+        let line = |millis, text: &str| Line {
+            time: ms(millis),
+            text: text.to_string(),
+        };
+        assert_eq!(
+            vec![
+                line(1400, "first"),
+                line(2900, "repeated"),
+                line(4900, ""),
+                line(10100, "repeated"),
+                line(62245, "[verse]"),
+            ],
+            lyrics.lines
+        );
+        assert_eq!(None, lyrics.line_at(ms(1000)));
+        assert_eq!(Some("first"), lyrics.line_at(ms(1400)));
+        assert_eq!(Some(""), lyrics.line_at(ms(6000)));
+        assert_eq!(Some(ms(2900)), lyrics.next_change(ms(1400)));
+        assert_eq!(None, lyrics.next_change(ms(70000)));
+    }
+
+    #[test]
+    fn find() {
+        assert_eq!(
+            Some(PathBuf::from("/music/a b/ü.flac")),
+            file_path("file:///music/a%20b/%C3%BC.flac")
+        );
+        assert_eq!(None, file_path("https://example.com/a.flac"));
+        let dir = env::temp_dir().join(format!("mpris-dbus-lyrics-{}", process::id()));
+        let (music, lyrics) = (dir.join("music"), dir.join("lyrics"));
+        fs::create_dir_all(&music).unwrap();
+        fs::create_dir_all(&lyrics).unwrap();
+        let track = Track {
+            player: String::new(),
+            url: Some(format!("file://{}/song.flac", music.display())),
+            artist: Some("A/B".to_string()),
+            title: Some("T".to_string()),
+        };
+        let dirs = [lyrics.clone()];
+        assert_eq!(None, track.find(&dirs));
+        fs::write(lyrics.join("A_B - T.lrc"), "").unwrap();
+        assert_eq!(Some(lyrics.join("A_B - T.lrc")), track.find(&dirs));
+        fs::write(lyrics.join("song.lrc"), "").unwrap();
+        assert_eq!(Some(lyrics.join("song.lrc")), track.find(&dirs));
+        fs::write(music.join("song.lrc"), "").unwrap();
+        assert_eq!(Some(music.join("song.lrc")), track.find(&dirs));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn current() {
+        let lyrics = Arc::new(Lyrics::parse("[00:00.05]a\n[00:00.10]b\n[00:00.12]b\n"));
+        let position = Position {
+            position: Duration::ZERO,
+            at: Instant::now(),
+            playing: true,
+            rate: 2.0,
+        };
+        let positions = stream::iter([Ok(position)]).chain(stream::pending());
+        let lines: Vec<_> = timeout(
+            Duration::from_secs(1),
+            current_line(lyrics, positions).take(3).collect(),
+        )
+        .await
+        .unwrap();
+        let lines: Vec<_> = lines.into_iter().map(Result::unwrap).collect();
+        assert_eq!(
+            vec![None, Some("a".to_string()), Some("b".to_string())],
+            lines
+        );
+    }
+}
@@ -1,20 +1,32 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Arc,
+    time::Duration,
+};
 
-use tracing::{debug_span, event, info, Instrument, Level};
+use futures_util::join;
+use tracing::{debug_span, event, info, warn, Instrument, Level};
 use zbus::{
     export::futures_util::{
         stream::{empty, select},
         Stream, StreamExt,
     },
-    zvariant::OwnedValue,
+    names::BusName,
+    zvariant::{Array, OwnedValue},
     Connection,
 };
 
 use crate::{
     proxies::{LoopStatus, PlaybackStatus, PlayerProxy},
-    util::{ResultExt, StreamExt2},
+    stream::StreamExt2,
+    util::{follow_owner, ResultExt},
+    Error,
 };
 
+/// Default window in which property changes are combined into a single [`PlayerInfo`].
+/// Players usually change several properties at once on track changes.
+pub const COALESCE_WINDOW: Duration = Duration::from_millis(30);
+
 #[derive(Debug, Clone,PartialEq)]
 pub struct PlayerInfo {
     pub playback_status: PlaybackStatus,
@@ -25,6 +37,60 @@ pub struct PlayerInfo {
     pub can_go_previous: bool,
     pub can_play: bool,
     pub can_pause: bool,
+    /// Properties the player does not implement. They are set to their default values.
+    pub unsupported: BTreeSet<&'static str>,
+}
+
+/// The values used for properties the player fails to provide, as defined by the MPRIS
+/// specification.
+impl Default for PlayerInfo {
+    fn default() -> Self {
+        PlayerInfo {
+            playback_status: PlaybackStatus::Stopped,
+            loop_status: None,
+            shuffle: None,
+            metadata: Arc::default(),
+            can_go_next: false,
+            can_go_previous: false,
+            can_play: false,
+            can_pause: false,
+            unsupported: BTreeSet::new(),
+        }
+    }
+}
+
+impl PlayerInfo {
+    /// The metadata entry `key` as text. Lists like `xesam:artist` are joined with commas.
+    pub fn metadata_text(&self, key: &str) -> Option<String> {
+        let value = self.metadata.get(key)?;
+        if let Some(s) = value.downcast_ref::<str>() {
+            return Some(s.to_string());
+        }
+        if let Some(array) = value.downcast_ref::<Array>() {
+            let values: Vec<&str> = array.iter().filter_map(|v| v.downcast_ref()).collect();
+            return Some(values.join(", "));
+        }
+        value
+            .downcast_ref::<i64>()
+            .map(|v| v.to_string())
+            .or_else(|| value.downcast_ref::<u64>().map(|v| v.to_string()))
+    }
+
+    /// Sets `property` to its default value.
+    fn reset(&mut self, property: &str) {
+        let default = PlayerInfo::default();
+        match property {
+            "PlaybackStatus" => self.playback_status = default.playback_status,
+            "LoopStatus" => self.loop_status = default.loop_status,
+            "Shuffle" => self.shuffle = default.shuffle,
+            "Metadata" => self.metadata = default.metadata,
+            "CanGoNext" => self.can_go_next = default.can_go_next,
+            "CanGoPrevious" => self.can_go_previous = default.can_go_previous,
+            "CanPlay" => self.can_play = default.can_play,
+            "CanPause" => self.can_pause = default.can_pause,
+            _ => {}
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -37,153 +103,223 @@ enum PlayerInfoUpdate {
     CanGoPrevious(bool),
     CanPlay(bool),
     CanPause(bool),
+    /// Reading the property failed, `unsupported` if the player does not implement it.
+    Failed {
+        property: &'static str,
+        unsupported: bool,
+    },
     Nothing,
 }
 
-pub async fn player_info<'a>(
-    name: &'a str,
-    conn: &Connection,
-) -> zbus::Result<impl Stream<Item = zbus::Result<PlayerInfo>> + 'a> {
-    let span = debug_span!("player_info", name);
-    async {
-        let proxy = PlayerProxy::builder(conn)
-            .destination(name)?
-            .build()
-            .await?;
-        let (
-            playback_status_stream,
-            loop_status_stream,
-            shuffle_stream,
-            metadata_stream,
-            can_go_next_stream,
-            can_go_previous_stream,
-            can_play_stream,
-            can_pause_stream,
-        ) = tokio::join!(
-            proxy.receive_playback_status_changed(),
-            proxy.receive_loop_status_changed(),
-            proxy.receive_shuffle_changed(),
-            proxy.receive_metadata_changed(),
-            proxy.receive_can_go_next_changed(),
-            proxy.receive_can_go_previous_changed(),
-            proxy.receive_can_play_changed(),
-            proxy.receive_can_pause_changed()
-        );
+impl PlayerInfoUpdate {
+    /// The property updated by `self`.
+    fn property(&self) -> Option<&'static str> {
+        match self {
+            PlayerInfoUpdate::PlaybackStatus(_) => Some("PlaybackStatus"),
+            PlayerInfoUpdate::LoopStatus(_) => Some("LoopStatus"),
+            PlayerInfoUpdate::Shuffle(_) => Some("Shuffle"),
+            PlayerInfoUpdate::Metadata(_) => Some("Metadata"),
+            PlayerInfoUpdate::CanGoNext(_) => Some("CanGoNext"),
+            PlayerInfoUpdate::CanGoPrevious(_) => Some("CanGoPrevious"),
+            PlayerInfoUpdate::CanPlay(_) => Some("CanPlay"),
+            PlayerInfoUpdate::CanPause(_) => Some("CanPause"),
+            PlayerInfoUpdate::Failed { .. } | PlayerInfoUpdate::Nothing => None,
+        }
+    }
+}
 
-        let playback_status_stream = playback_status_stream.then(|event| async move {
-            zbus::Result::Ok(PlayerInfoUpdate::PlaybackStatus(event.get().await?))
-        }).inspect(|v|event!(Level::DEBUG,playback_status = ?v)).instrument_stream(debug_span!("playback_status"));
-        let loop_status_stream = loop_status_stream.then(|event| async move {
-            zbus::Result::Ok(PlayerInfoUpdate::LoopStatus(event.get().await?))
-        }).inspect(|v|event!(Level::DEBUG,loop_status = ?v)).instrument_stream(debug_span!("loop_status"));
-        let shuffle_stream = shuffle_stream.then(|event| async move {
-            zbus::Result::Ok(PlayerInfoUpdate::Shuffle(event.get().await?))
-        }).inspect(|v|event!(Level::DEBUG,shuffle = ?v)).instrument_stream(debug_span!("shuffle"));
-        let metadata_stream = metadata_stream.then(|event| async move {
-            zbus::Result::Ok(PlayerInfoUpdate::Metadata(event.get().await?))
-        }).inspect(|v|event!(Level::DEBUG,metadata = ?v)).instrument_stream(debug_span!("metadata"));
-        let can_go_next_stream = can_go_next_stream.then(|event| async move {
-            zbus::Result::Ok(PlayerInfoUpdate::CanGoNext(event.get().await?))
-        }).inspect(|v|event!(Level::DEBUG,can_go_next = ?v)).instrument_stream(debug_span!("can_go_next"));
-        let can_go_previous_stream = can_go_previous_stream.then(|event| async move {
-            zbus::Result::Ok(PlayerInfoUpdate::CanGoPrevious(event.get().await?))
-        }).inspect(|v|event!(Level::DEBUG,can_go_previous = ?v)).instrument_stream(debug_span!("can_go_previous"));
-        let can_play_stream = can_play_stream.then(|event| async move {
-            zbus::Result::Ok(PlayerInfoUpdate::CanPlay(event.get().await?))
-        }).inspect(|v|event!(Level::DEBUG,can_play = ?v)).instrument_stream(debug_span!("can_play"));
-        let can_pause_stream = can_pause_stream.then(|event| async move {
-            zbus::Result::Ok(PlayerInfoUpdate::CanPause(event.get().await?))
-        }).inspect(|v|event!(Level::DEBUG,can_pause = ?v)).instrument_stream(debug_span!("can_pause"));
-
-        info!("connected to {name}");
-
-        let (
-            playback_status,
-            loop_status,
-            shuffle,
-            metadata,
-            can_go_next,
-            can_go_previous,
-            can_play,
-            can_pause,
-        ) = tokio::join!(
-            proxy.playback_status(),
-            proxy.loop_status(),
-            proxy.shuffle(),
-            proxy.metadata(),
-            proxy.can_go_next(),
-            proxy.can_go_previous(),
-            proxy.can_play(),
-            proxy.can_pause()
-        );
-        let info = PlayerInfo {
-            playback_status: playback_status?,
-            loop_status: loop_status.as_ref().ok().copied(),
-            shuffle: shuffle.as_ref().ok().copied(),
-            metadata: Arc::new(metadata?),
-            can_go_next: can_go_next?,
-            can_go_previous: can_go_previous?,
-            can_play: can_play?,
-            can_pause: can_pause?,
-        };
+/// Properties players may leave out.
+const OPTIONAL: [&str; 2] = ["LoopStatus", "Shuffle"];
 
-        let loop_status_stream = if loop_status.is_ok() {
-            loop_status_stream.right_stream()
-        } else {
-            empty().left_stream()
-        };
+/// Logs a failed read of `property` instead of failing the whole stream. Returns wether the
+/// player does not implement the property.
+fn failed(property: &'static str, e: zbus::Error) -> bool {
+    let error = Error::property(property, e);
+    match error {
+        Error::PropertyUnsupported { .. } if OPTIONAL.contains(&property) => {
+            event!(
+                Level::DEBUG,
+                "optional property {property} is not supported"
+            );
+            true
+        }
+        Error::PropertyUnsupported { .. } => {
+            warn!(?error, "{error}");
+            true
+        }
+        _ => {
+            warn!(?error, "{error}");
+            false
+        }
+    }
+}
 
-        let shuffle_stream = if shuffle.is_ok() {
-            shuffle_stream.right_stream()
-        } else {
-            empty().left_stream()
-        };
+fn update<T>(
+    property: &'static str,
+    value: zbus::Result<T>,
+    update: fn(T) -> PlayerInfoUpdate,
+) -> PlayerInfoUpdate {
+    match value {
+        Ok(value) => update(value),
+        Err(e) => PlayerInfoUpdate::Failed {
+            property,
+            unsupported: failed(property, e),
+        },
+    }
+}
 
-        let update_stream = select(
-            select(
-                select(playback_status_stream, loop_status_stream),
-                select(shuffle_stream, metadata_stream),
+/// The value of a property, or `default` if the player failed to provide it. Only properties
+/// the player does not implement are added to `unsupported_properties`.
+fn or_default<T>(
+    property: &'static str,
+    value: zbus::Result<T>,
+    default: T,
+    unsupported_properties: &mut BTreeSet<&'static str>,
+) -> T {
+    value.unwrap_or_else(|e| {
+        if failed(property, e) {
+            unsupported_properties.insert(property);
+        }
+        default
+    })
+}
+
+/// Applies an update of a property to `fold`.
+fn apply(fold: &mut PlayerInfo, new: PlayerInfoUpdate) {
+    if let Some(property) = new.property() {
+        fold.unsupported.remove(property);
+    }
+    match new {
+        PlayerInfoUpdate::PlaybackStatus(status) => {
+            fold.playback_status = status;
+        }
+        PlayerInfoUpdate::LoopStatus(status) => {
+            fold.loop_status = Some(status);
+        }
+        PlayerInfoUpdate::Shuffle(shuffle) => {
+            fold.shuffle = Some(shuffle);
+        }
+        PlayerInfoUpdate::Metadata(metadata) => {
+            fold.metadata = Arc::new(metadata);
+        }
+        PlayerInfoUpdate::CanGoNext(next) => {
+            fold.can_go_next = next;
+        }
+        PlayerInfoUpdate::CanGoPrevious(previous) => {
+            fold.can_go_previous = previous;
+        }
+        PlayerInfoUpdate::CanPlay(play) => {
+            fold.can_play = play;
+        }
+        PlayerInfoUpdate::CanPause(pause) => {
+            fold.can_pause = pause;
+        }
+        PlayerInfoUpdate::Failed {
+            property,
+            unsupported,
+        } => {
+            // the same as for a failed initial read
+            fold.reset(property);
+            if unsupported {
+                fold.unsupported.insert(property);
+            }
+        }
+        PlayerInfoUpdate::Nothing => {}
+    }
+}
+
+/// Property values as read from a player.
+pub(crate) struct Properties {
+    pub playback_status: zbus::Result<PlaybackStatus>,
+    pub loop_status: zbus::Result<LoopStatus>,
+    pub shuffle: zbus::Result<bool>,
+    pub metadata: zbus::Result<HashMap<String, OwnedValue>>,
+    pub can_go_next: zbus::Result<bool>,
+    pub can_go_previous: zbus::Result<bool>,
+    pub can_play: zbus::Result<bool>,
+    pub can_pause: zbus::Result<bool>,
+}
+
+impl Properties {
+    /// The info for the properties, using defaults for the ones the player failed to provide.
+    pub(crate) fn into_info(self) -> PlayerInfo {
+        let mut unsupported = BTreeSet::new();
+        let default = PlayerInfo::default();
+        PlayerInfo {
+            playback_status: or_default(
+                "PlaybackStatus",
+                self.playback_status,
+                default.playback_status,
+                &mut unsupported,
             ),
-            select(
-                select(can_go_next_stream, can_go_previous_stream),
-                select(can_play_stream, can_pause_stream),
+            loop_status: or_default(
+                "LoopStatus",
+                self.loop_status.map(Some),
+                default.loop_status,
+                &mut unsupported,
             ),
-        )
-        .with_initial_value(Ok(PlayerInfoUpdate::Nothing));
-
-        let stream = update_stream.fold_map(info, |new, mut fold| {
-            match new {
-                Err(e) => return (Err(e), fold),
-                Ok(PlayerInfoUpdate::PlaybackStatus(status)) => {
-                    fold.playback_status = status;
-                }
-                Ok(PlayerInfoUpdate::LoopStatus(status)) => {
-                    fold.loop_status = Some(status);
-                }
-                Ok(PlayerInfoUpdate::Shuffle(shuffle)) => {
-                    fold.shuffle = Some(shuffle);
-                }
-                Ok(PlayerInfoUpdate::Metadata(metadata)) => {
-                    fold.metadata = Arc::new(metadata);
-                }
-                Ok(PlayerInfoUpdate::CanGoNext(next)) => {
-                    fold.can_go_next = next;
-                }
-                Ok(PlayerInfoUpdate::CanGoPrevious(previous)) => {
-                    fold.can_go_previous = previous;
-                }
-                Ok(PlayerInfoUpdate::CanPlay(play)) => {
-                    fold.can_play = play;
-                }
-                Ok(PlayerInfoUpdate::CanPause(pause)) => {
-                    fold.can_pause = pause;
-                }
-                Ok(PlayerInfoUpdate::Nothing) => {}
-            }
-            let fold2 = fold.clone();
-            (Ok(fold), fold2)
-        }).filter_no_change();
-        Ok(stream)
+            shuffle: or_default(
+                "Shuffle",
+                self.shuffle.map(Some),
+                default.shuffle,
+                &mut unsupported,
+            ),
+            metadata: or_default(
+                "Metadata",
+                self.metadata.map(Arc::new),
+                default.metadata,
+                &mut unsupported,
+            ),
+            can_go_next: or_default(
+                "CanGoNext",
+                self.can_go_next,
+                default.can_go_next,
+                &mut unsupported,
+            ),
+            can_go_previous: or_default(
+                "CanGoPrevious",
+                self.can_go_previous,
+                default.can_go_previous,
+                &mut unsupported,
+            ),
+            can_play: or_default("CanPlay", self.can_play, default.can_play, &mut unsupported),
+            can_pause: or_default(
+                "CanPause",
+                self.can_pause,
+                default.can_pause,
+                &mut unsupported,
+            ),
+            unsupported,
+        }
+    }
+}
+
+/// Follows the player `name`, reporting an error while it is not on the bus and
+/// reconnecting once it reappears.
+pub async fn player_info(
+    name: impl Into<BusName<'static>>,
+    conn: &Connection,
+) -> crate::Result<impl Stream<Item = crate::Result<PlayerInfo>> + 'static> {
+    player_info_with(name, conn, COALESCE_WINDOW).await
+}
+
+/// Like [`player_info`], but combines the property changes arriving within `window` instead
+/// of [`COALESCE_WINDOW`].
+pub async fn player_info_with(
+    name: impl Into<BusName<'static>>,
+    conn: &Connection,
+    window: Duration,
+) -> crate::Result<impl Stream<Item = crate::Result<PlayerInfo>> + 'static> {
+    let name = name.into();
+    let span = debug_span!("player_info", %name, ?window);
+    async {
+        let owned = conn.clone();
+        let vanished = Error::PlayerVanished(name.to_string());
+        let stream = follow_owner(conn, name.clone(), false, vanished, move || {
+            connect(name.clone(), owned.clone())
+        })
+        .await?;
+        Ok(stream.coalesce(window).filter_no_change())
     }
     .instrument(span.clone())
     .await
@@ -193,3 +329,209 @@ pub async fn player_info<'a>(
             .instrument_stream(span)
     })
 }
+
+async fn connect(
+    name: BusName<'static>,
+    conn: Connection,
+) -> crate::Result<impl Stream<Item = crate::Result<PlayerInfo>>> {
+    let proxy = PlayerProxy::builder(&conn)
+        .destination(name.clone())?
+        .build()
+        .await?;
+    let (
+        playback_status_stream,
+        loop_status_stream,
+        shuffle_stream,
+        metadata_stream,
+        can_go_next_stream,
+        can_go_previous_stream,
+        can_play_stream,
+        can_pause_stream,
+    ) = join!(
+        proxy.receive_playback_status_changed(),
+        proxy.receive_loop_status_changed(),
+        proxy.receive_shuffle_changed(),
+        proxy.receive_metadata_changed(),
+        proxy.receive_can_go_next_changed(),
+        proxy.receive_can_go_previous_changed(),
+        proxy.receive_can_play_changed(),
+        proxy.receive_can_pause_changed()
+    );
+
+    let playback_status_stream = playback_status_stream.then(|event| async move {
+        update("PlaybackStatus", event.get().await, PlayerInfoUpdate::PlaybackStatus)
+    }).inspect(|v|event!(Level::DEBUG,playback_status = ?v)).instrument_stream(debug_span!("playback_status"));
+    let loop_status_stream = loop_status_stream.then(|event| async move {
+        update("LoopStatus", event.get().await, PlayerInfoUpdate::LoopStatus)
+    }).inspect(|v|event!(Level::DEBUG,loop_status = ?v)).instrument_stream(debug_span!("loop_status"));
+    let shuffle_stream = shuffle_stream.then(|event| async move {
+        update("Shuffle", event.get().await, PlayerInfoUpdate::Shuffle)
+    }).inspect(|v|event!(Level::DEBUG,shuffle = ?v)).instrument_stream(debug_span!("shuffle"));
+    let metadata_stream = metadata_stream.then(|event| async move {
+        update("Metadata", event.get().await, PlayerInfoUpdate::Metadata)
+    }).inspect(|v|event!(Level::DEBUG,metadata = ?v)).instrument_stream(debug_span!("metadata"));
+    let can_go_next_stream = can_go_next_stream.then(|event| async move {
+        update("CanGoNext", event.get().await, PlayerInfoUpdate::CanGoNext)
+    }).inspect(|v|event!(Level::DEBUG,can_go_next = ?v)).instrument_stream(debug_span!("can_go_next"));
+    let can_go_previous_stream = can_go_previous_stream.then(|event| async move {
+        update("CanGoPrevious", event.get().await, PlayerInfoUpdate::CanGoPrevious)
+    }).inspect(|v|event!(Level::DEBUG,can_go_previous = ?v)).instrument_stream(debug_span!("can_go_previous"));
+    let can_play_stream = can_play_stream.then(|event| async move {
+        update("CanPlay", event.get().await, PlayerInfoUpdate::CanPlay)
+    }).inspect(|v|event!(Level::DEBUG,can_play = ?v)).instrument_stream(debug_span!("can_play"));
+    let can_pause_stream = can_pause_stream.then(|event| async move {
+        update("CanPause", event.get().await, PlayerInfoUpdate::CanPause)
+    }).inspect(|v|event!(Level::DEBUG,can_pause = ?v)).instrument_stream(debug_span!("can_pause"));
+
+    info!("connected to {name}");
+
+    let (
+        playback_status,
+        loop_status,
+        shuffle,
+        metadata,
+        can_go_next,
+        can_go_previous,
+        can_play,
+        can_pause,
+    ) = join!(
+        proxy.playback_status(),
+        proxy.loop_status(),
+        proxy.shuffle(),
+        proxy.metadata(),
+        proxy.can_go_next(),
+        proxy.can_go_previous(),
+        proxy.can_play(),
+        proxy.can_pause()
+    );
+    let info = Properties {
+        playback_status,
+        loop_status,
+        shuffle,
+        metadata,
+        can_go_next,
+        can_go_previous,
+        can_play,
+        can_pause,
+    }
+    .into_info();
+    let (loop_status_supported, shuffle_supported) = (
+        !info.unsupported.contains("LoopStatus"),
+        !info.unsupported.contains("Shuffle"),
+    );
+
+    let loop_status_stream = if loop_status_supported {
+        loop_status_stream.right_stream()
+    } else {
+        empty().left_stream()
+    };
+
+    let shuffle_stream = if shuffle_supported {
+        shuffle_stream.right_stream()
+    } else {
+        empty().left_stream()
+    };
+
+    let update_stream = select(
+        select(
+            select(playback_status_stream, loop_status_stream),
+            select(shuffle_stream, metadata_stream),
+        ),
+        select(
+            select(can_go_next_stream, can_go_previous_stream),
+            select(can_play_stream, can_pause_stream),
+        ),
+    )
+    .with_initial_value(PlayerInfoUpdate::Nothing);
+
+    let stream = update_stream.fold_map(info, |new, fold| {
+        apply(fold, new);
+        Ok(fold.clone())
+    });
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use zbus::{fdo, zvariant::Value};
+
+    use super::{apply, PlayerInfo, PlayerInfoUpdate, Properties};
+    use crate::proxies::PlaybackStatus;
+
+    fn not_supported<T>() -> zbus::Result<T> {
+        Err(fdo::Error::NotSupported("not supported".to_string()).into())
+    }
+
+    fn no_reply<T>() -> zbus::Result<T> {
+        Err(fdo::Error::NoReply("timeout".to_string()).into())
+    }
+
+    #[test]
+    fn initial_read() {
+        let info = Properties {
+            playback_status: Ok(PlaybackStatus::Playing),
+            loop_status: not_supported(),
+            shuffle: no_reply(),
+            metadata: Ok(HashMap::new()),
+            can_go_next: Ok(true),
+            can_go_previous: not_supported(),
+            can_play: no_reply(),
+            can_pause: Ok(true),
+        }
+        .into_info();
+        assert_eq!(None, info.shuffle);
+        assert!(!info.can_play);
+        assert!(!info.can_go_previous);
+        // only properties the player does not implement are unsupported
+        assert_eq!(
+            vec!["CanGoPrevious", "LoopStatus"],
+            info.unsupported.into_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn failed_updates() {
+        let mut info = PlayerInfo {
+            playback_status: PlaybackStatus::Playing,
+            can_play: true,
+            ..Default::default()
+        };
+        apply(
+            &mut info,
+            PlayerInfoUpdate::Metadata(HashMap::from([(
+                "xesam:title".to_string(),
+                Value::from("title").into(),
+            )])),
+        );
+        assert_eq!(Some("title".to_string()), info.metadata_text("xesam:title"));
+
+        // a failed update uses the same defaults as a failed initial read
+        for property in ["Metadata", "PlaybackStatus"] {
+            apply(
+                &mut info,
+                PlayerInfoUpdate::Failed {
+                    property,
+                    unsupported: false,
+                },
+            );
+        }
+        assert!(info.metadata.is_empty());
+        assert_eq!(PlaybackStatus::Stopped, info.playback_status);
+        assert!(info.unsupported.is_empty());
+
+        apply(
+            &mut info,
+            PlayerInfoUpdate::Failed {
+                property: "CanPlay",
+                unsupported: true,
+            },
+        );
+        assert!(!info.can_play);
+        assert!(info.unsupported.contains("CanPlay"));
+        apply(&mut info, PlayerInfoUpdate::CanPlay(true));
+        assert!(info.can_play);
+        assert!(info.unsupported.is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+use std::time::{Duration, Instant};
+
+use futures_util::join;
+use tracing::{debug_span, event, info, Instrument, Level};
+use zbus::{
+    export::futures_util::{stream::select, Stream, StreamExt},
+    names::BusName,
+    Connection,
+};
+
+use crate::{
+    player_info::COALESCE_WINDOW,
+    proxies::{PlaybackStatus, PlayerProxy},
+    stream::StreamExt2,
+    util::{follow_owner, ResultExt},
+    Error,
+};
+
+/// Playback position of a player when it was read.
+///
+/// Players do not announce position changes during playback, so the position is read again
+/// only on seeks and changes of the playback status or rate and extrapolated in between.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub position: Duration,
+    /// When the position was read.
+    pub at: Instant,
+    pub playing: bool,
+    /// Between 0 and [`MAX_RATE`] when read from a player, see [`rate`].
+    pub rate: f64,
+}
+
+/// The fastest rate a position is extrapolated with.
+pub const MAX_RATE: f64 = 100.0;
+/// Slower rates are treated as a stopped position.
+const MIN_RATE: f64 = 0.01;
+
+/// Keeps the reported rate in a range durations can be scaled with: players that report an
+/// invalid rate are assumed to play at the normal rate.
+fn rate(rate: f64) -> f64 {
+    if !rate.is_finite() {
+        1.0
+    } else if rate < MIN_RATE {
+        0.0
+    } else {
+        rate.min(MAX_RATE)
+    }
+}
+
+impl Position {
+    /// The position at `now`, assuming the player kept playing at its rate.
+    pub fn extrapolate(&self, now: Instant) -> Duration {
+        if !self.playing || now <= self.at {
+            return self.position;
+        }
+        let elapsed = (now - self.at).as_secs_f64() * self.rate.max(0.0);
+        self.position
+            .saturating_add(Duration::try_from_secs_f64(elapsed).unwrap_or(Duration::MAX))
+    }
+}
+
+/// Follows the position of the player `name`, reporting an error while it is not on the bus
+/// and reconnecting once it reappears.
+pub async fn position(
+    name: impl Into<BusName<'static>>,
+    conn: &Connection,
+) -> crate::Result<impl Stream<Item = crate::Result<Position>> + 'static> {
+    let name = name.into();
+    let span = debug_span!("position", %name);
+    async {
+        let owned = conn.clone();
+        let vanished = Error::PlayerVanished(name.to_string());
+        let stream = follow_owner(conn, name.clone(), false, vanished, move || {
+            connect(name.clone(), owned.clone())
+        })
+        .await?;
+        Ok(stream.coalesce(COALESCE_WINDOW))
+    }
+    .instrument(span.clone())
+    .await
+    .trace_err_span(&span)
+    .map(|s| {
+        s.inspect(|r| event!(Level::DEBUG,position = ?r))
+            .instrument_stream(span)
+    })
+}
+
+async fn connect(
+    name: BusName<'static>,
+    conn: Connection,
+) -> crate::Result<impl Stream<Item = crate::Result<Position>>> {
+    let proxy = PlayerProxy::builder(&conn)
+        .destination(name.clone())?
+        .build()
+        .await?;
+    let (seeked, playback_status, rate) = join!(
+        proxy.receive_seeked(),
+        proxy.receive_playback_status_changed(),
+        proxy.receive_rate_changed()
+    );
+    let changes = select(
+        seeked?.map(drop),
+        select(playback_status.map(drop), rate.map(drop)),
+    );
+    info!("following the position of {name}");
+    Ok(changes.with_initial_value(()).then(move |()| {
+        let proxy = proxy.clone();
+        async move { read(&proxy).await }
+    }))
+}
+
+async fn read(proxy: &PlayerProxy<'static>) -> crate::Result<Position> {
+    let (position, playback_status, rate) =
+        join!(proxy.position(), proxy.playback_status(), proxy.rate());
+    let position = position.map_err(|e| Error::property("Position", e))?;
+    Ok(Position {
+        position: Duration::from_micros(position.max(0) as u64),
+        at: Instant::now(),
+        playing: playback_status.map_err(|e| Error::property("PlaybackStatus", e))?
+            == PlaybackStatus::Playing,
+        // the rate is optional
+        rate: rate.map_or(1.0, self::rate),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{rate, Position, MAX_RATE};
+
+    #[test]
+    fn extrapolate() {
+        let at = Instant::now();
+        let mut position = Position {
+            position: Duration::from_secs(10),
+            at,
+            playing: true,
+            rate: 2.0,
+        };
+        assert_eq!(
+            Duration::from_secs(16),
+            position.extrapolate(at + Duration::from_secs(3))
+        );
+        assert_eq!(Duration::from_secs(10), position.extrapolate(at));
+        position.rate = f64::INFINITY;
+        assert_eq!(
+            Duration::MAX,
+            position.extrapolate(at + Duration::from_secs(3))
+        );
+        position.playing = false;
+        assert_eq!(
+            Duration::from_secs(10),
+            position.extrapolate(at + Duration::from_secs(3))
+        );
+    }
+
+    #[test]
+    fn rates() {
+        assert_eq!(1.5, rate(1.5));
+        assert_eq!(1.0, rate(f64::NAN));
+        assert_eq!(1.0, rate(f64::INFINITY));
+        assert_eq!(0.0, rate(-1.0));
+        assert_eq!(0.0, rate(1e-300));
+        assert_eq!(MAX_RATE, rate(1e300));
+    }
+}
@@ -20,7 +20,14 @@ use std::{collections::HashMap, ops::Deref};
 use zbus::{dbus_interface, SignalContext};
 
 #[cfg(feature = "hide_server")]
-use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Mutex, OnceLock},
+};
+
+#[cfg(any(feature = "hide_proxy", feature = "hide_server"))]
+/// Name of the flag backing the `Hidden` property.
+pub const HIDDEN_FLAG: &str = "hidden";
 
 #[cfg(feature = "hide_proxy")]
 #[dbus_proxy(
@@ -29,23 +36,179 @@ use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
     default_path = "/com/github/robinmarchart/mprisutils"
 )]
 pub trait HideState {
+    /// Toggle method, returns the previous hidden state
     fn toggle(&self) -> Result<bool>;
     #[dbus_proxy(property)]
     fn hidden(&self) -> Result<bool>;
     #[dbus_proxy(property)]
     fn set_hidden(&self, val: bool) -> Result<()>;
+
+    /// GetFlag method, unknown flags are `false`
+    fn get_flag(&self, name: &str) -> Result<bool>;
+
+    /// SetFlag method
+    fn set_flag(&self, name: &str, value: bool) -> Result<()>;
+
+    /// ToggleFlag method, returns the new value
+    fn toggle_flag(&self, name: &str) -> Result<bool>;
+
+    /// GetValue method, unknown values are empty
+    fn get_value(&self, name: &str) -> Result<String>;
+
+    /// SetValue method, an empty value removes the entry
+    fn set_value(&self, name: &str, value: &str) -> Result<()>;
+
+    /// HiddenPlayers property
+    #[dbus_proxy(property)]
+    fn hidden_players(&self) -> Result<Vec<String>>;
+
+    /// SetPlayerHidden method
+    fn set_player_hidden(&self, player: &str, hidden: bool) -> Result<()>;
+
+    /// TogglePlayer method, returns wether the player is now hidden
+    fn toggle_player(&self, player: &str) -> Result<bool>;
+
+    /// FlagChanged signal
+    #[dbus_proxy(signal)]
+    fn flag_changed(&self, name: &str, value: bool) -> Result<()>;
+
+    /// ValueChanged signal
+    #[dbus_proxy(signal)]
+    fn value_changed(&self, name: &str, value: &str) -> Result<()>;
+}
+
+/// Named flags and values stored by the [`HideServer`].
+#[cfg(feature = "hide_server")]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct State {
+    pub flags: BTreeMap<String, bool>,
+    pub values: BTreeMap<String, String>,
+    pub hidden_players: BTreeSet<String>,
 }
 
+#[cfg(feature = "hide_server")]
+impl State {
+    pub fn hidden(&self) -> bool {
+        self.flags.get(HIDDEN_FLAG).copied().unwrap_or(false)
+    }
+}
+
+#[cfg(feature = "hide_server")]
+type ChangeHook = Box<dyn Fn(&State) + Send + Sync>;
+
 #[cfg(feature = "hide_server")]
 pub struct HideServer {
-    hidden: AtomicBool,
+    /// The state and the number of changes made to it.
+    state: Mutex<(State, u64)>,
+    /// The number of the last change passed to the change hook.
+    persisted: Mutex<u64>,
+    ctxt: OnceLock<SignalContext<'static>>,
+    on_change: Option<ChangeHook>,
 }
 
 #[cfg(feature = "hide_server")]
 impl HideServer {
-    pub fn new(state: bool) -> Self {
+    pub fn new(hidden: bool) -> Self {
+        let mut state = State::default();
+        state.flags.insert(HIDDEN_FLAG.to_string(), hidden);
+        Self::with_state(state)
+    }
+
+    pub fn with_state(state: State) -> Self {
         Self {
-            hidden: AtomicBool::new(state),
+            state: Mutex::new((state, 0)),
+            persisted: Mutex::new(0),
+            ctxt: OnceLock::new(),
+            on_change: None,
+        }
+    }
+
+    /// Called with a snapshot of the new state after every change.
+    pub fn on_change(mut self, hook: impl Fn(&State) + Send + Sync + 'static) -> Self {
+        self.on_change = Some(Box::new(hook));
+        self
+    }
+
+    /// Signal context used to announce changes made through the `Hidden` property setter.
+    /// Has to be set once the server is registered.
+    pub fn attach(&self, ctxt: SignalContext<'static>) {
+        if self.ctxt.set(ctxt).is_err() {
+            warn!("signal context already attached");
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state.lock().unwrap().0.clone()
+    }
+
+    /// Applies `update`, which returns whether it changed the state. The change hook is called
+    /// after the lock is released, so it does not block other changes, and is skipped if a
+    /// later change was already passed to it.
+    fn update<T>(&self, update: impl FnOnce(&mut State) -> (bool, T)) -> (bool, T) {
+        let (changed, result, snapshot) = {
+            let mut guard = self.state.lock().unwrap();
+            let (state, changes) = &mut *guard;
+            let (changed, result) = update(state);
+            if changed {
+                *changes += 1;
+            }
+            let snapshot = (changed && self.on_change.is_some()).then(|| (state.clone(), *changes));
+            (changed, result, snapshot)
+        };
+        if let (Some(hook), Some((state, change))) = (&self.on_change, snapshot) {
+            let mut persisted = self.persisted.lock().unwrap();
+            if change > *persisted {
+                hook(&state);
+                *persisted = change;
+            }
+        }
+        (changed, result)
+    }
+
+    fn update_flag(&self, name: &str, update: impl FnOnce(bool) -> bool) -> (bool, bool) {
+        self.update(|state| {
+            let old = state.flags.get(name).copied().unwrap_or(false);
+            let new = update(old);
+            state.flags.insert(name.to_string(), new);
+            (old != new, new)
+        })
+    }
+
+    fn update_player(&self, player: &str, update: impl FnOnce(bool) -> bool) -> (bool, bool) {
+        self.update(|state| {
+            let old = state.hidden_players.contains(player);
+            let new = update(old);
+            if new {
+                state.hidden_players.insert(player.to_string());
+            } else {
+                state.hidden_players.remove(player);
+            }
+            (old != new, new)
+        })
+    }
+
+    async fn announce_player(&self, ctxt: &SignalContext<'_>, player: &str, hidden: bool) {
+        info!("changed hidden state of {player} to {hidden}");
+        self.hidden_players_changed(ctxt)
+            .await
+            .err()
+            .into_iter()
+            .for_each(|e| warn!("{}", e));
+    }
+
+    async fn announce_flag(&self, ctxt: &SignalContext<'_>, name: &str, value: bool) {
+        info!("changed flag {name} to {value}");
+        Self::flag_changed(ctxt, name, value)
+            .await
+            .err()
+            .into_iter()
+            .for_each(|e| warn!("{}", e));
+        if name == HIDDEN_FLAG {
+            self.hidden_changed(ctxt)
+                .await
+                .err()
+                .into_iter()
+                .for_each(|e| warn!("{}", e));
         }
     }
 }
@@ -55,24 +218,141 @@ impl HideServer {
 impl HideServer {
     #[dbus_interface(property)]
     fn hidden(&self) -> bool {
-        self.hidden.load(Relaxed)
+        self.state.lock().unwrap().0.hidden()
     }
 
     #[dbus_interface(property)]
-    fn set_hidden(&self, new: bool) {
-        self.hidden.store(new, Relaxed)
+    async fn set_hidden(&self, new: bool) {
+        let (changed, new) = self.update_flag(HIDDEN_FLAG, |_| new);
+        if changed {
+            info!("changed flag {HIDDEN_FLAG} to {new}");
+            if let Some(ctxt) = self.ctxt.get() {
+                Self::flag_changed(ctxt, HIDDEN_FLAG, new)
+                    .await
+                    .err()
+                    .into_iter()
+                    .for_each(|e| warn!("{}", e));
+            }
+        }
     }
 
+    /// Returns the previous state, unlike `ToggleFlag`.
     async fn toggle(&self, #[zbus(signal_context)] ctxt: SignalContext<'_>) -> bool {
-        let res = self.hidden.fetch_xor(true, Relaxed);
-        info!("changed hidden state to {res}");
-        self.hidden_changed(&ctxt)
-            .await
-            .err()
-            .into_iter()
-            .for_each(|e| warn!("{}", e));
-        res
+        !self.toggle_flag(HIDDEN_FLAG, ctxt).await
     }
+
+    async fn get_flag(&self, name: &str) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .0
+            .flags
+            .get(name)
+            .copied()
+            .unwrap_or(false)
+    }
+
+    async fn set_flag(
+        &self,
+        name: &str,
+        value: bool,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+    ) {
+        let (changed, value) = self.update_flag(name, |_| value);
+        if changed {
+            self.announce_flag(&ctxt, name, value).await;
+        }
+    }
+
+    async fn toggle_flag(
+        &self,
+        name: &str,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+    ) -> bool {
+        let (_, value) = self.update_flag(name, |old| !old);
+        self.announce_flag(&ctxt, name, value).await;
+        value
+    }
+
+    async fn get_value(&self, name: &str) -> String {
+        self.state
+            .lock()
+            .unwrap()
+            .0
+            .values
+            .get(name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    async fn set_value(
+        &self,
+        name: &str,
+        value: &str,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+    ) {
+        let (changed, ()) = self.update(|state| {
+            let changed = if value.is_empty() {
+                state.values.remove(name).is_some()
+            } else {
+                state
+                    .values
+                    .insert(name.to_string(), value.to_string())
+                    .as_deref()
+                    != Some(value)
+            };
+            (changed, ())
+        });
+        if changed {
+            info!("changed value {name} to {value:?}");
+            Self::value_changed(&ctxt, name, value)
+                .await
+                .err()
+                .into_iter()
+                .for_each(|e| warn!("{}", e));
+        }
+    }
+
+    #[dbus_interface(property)]
+    fn hidden_players(&self) -> Vec<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .0
+            .hidden_players
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    async fn set_player_hidden(
+        &self,
+        player: &str,
+        hidden: bool,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+    ) {
+        let (changed, hidden) = self.update_player(player, |_| hidden);
+        if changed {
+            self.announce_player(&ctxt, player, hidden).await;
+        }
+    }
+
+    async fn toggle_player(
+        &self,
+        player: &str,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+    ) -> bool {
+        let (_, hidden) = self.update_player(player, |old| !old);
+        self.announce_player(&ctxt, player, hidden).await;
+        hidden
+    }
+
+    #[dbus_interface(signal)]
+    async fn flag_changed(ctxt: &SignalContext<'_>, name: &str, value: bool) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn value_changed(ctxt: &SignalContext<'_>, name: &str, value: &str)
+        -> zbus::Result<()>;
 }
 
 #[cfg(feature = "playerctld_proxy")]
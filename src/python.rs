@@ -0,0 +1,353 @@
+//! Python bindings, built as the `mpris_dbus` extension module with maturin.
+//!
+//! ```python
+//! import asyncio
+//! import mpris_dbus
+//!
+//! async def main():
+//!     async for info in mpris_dbus.hidden_active_player_info():
+//!         print(info)
+//!
+//! asyncio.run(main())
+//! ```
+//!
+//! Every item is a dict with the keys `hidden`, `error`, `transient` and `players`. While
+//! there is an active player, the keys of [`PlayerInfo`] are set as well. Errors of the
+//! streams are passed along in `error` like the library does, only failing to connect to
+//! the session bus raises `MprisError`.
+
+use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc, time::Duration};
+
+use pyo3::{
+    create_exception,
+    exceptions::{PyException, PyStopAsyncIteration, PyValueError},
+    prelude::*,
+    types::{PyDict, PyList, PyTuple},
+};
+use tokio::sync::Mutex;
+use zbus::{
+    export::futures_util::{
+        future::ready,
+        stream::{once, Stream},
+        StreamExt,
+    },
+    zvariant::{ObjectPath, OwnedValue, Value},
+    Connection,
+};
+
+use crate::{
+    hide::{hidden_active_player_info_with, AutoHide, HiddenInfo},
+    player_info::PlayerInfo,
+    proxies::{LoopStatus, PlayerProxy},
+    Error,
+};
+
+create_exception!(mpris_dbus, MprisError, PyException);
+
+impl From<Error> for PyErr {
+    fn from(e: Error) -> Self {
+        MprisError::new_err(e.to_string())
+    }
+}
+
+/// Drops `value` inside the runtime, unsubscribing from signals spawns tasks.
+fn drop_in_runtime<T>(value: T) {
+    let _runtime = pyo3_asyncio::tokio::get_runtime().enter();
+    drop(value);
+}
+
+type InfoStream = Pin<Box<dyn Stream<Item = crate::Result<Option<HiddenInfo>>> + Send>>;
+
+/// Async iterator over the info of the active player.
+#[pyclass(module = "mpris_dbus")]
+struct PlayerInfoIterator {
+    stream: Option<Arc<Mutex<InfoStream>>>,
+}
+
+impl PlayerInfoIterator {
+    /// Connects once the first item is requested, as creating the iterator is synchronous.
+    fn new<F, S>(connect: F) -> Self
+    where
+        F: Future<Output = crate::Result<S>> + Send + 'static,
+        S: Stream<Item = Option<HiddenInfo>> + Send + 'static,
+    {
+        let stream = once(connect)
+            .map(|stream| match stream {
+                Ok(stream) => stream.map(Ok).left_stream(),
+                Err(e) => once(ready(Err(e))).right_stream(),
+            })
+            .flatten();
+        Self {
+            stream: Some(Arc::new(Mutex::new(Box::pin(stream)))),
+        }
+    }
+}
+
+#[pymethods]
+impl PlayerInfoIterator {
+    fn __aiter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __anext__<'py>(&self, py: Python<'py>) -> PyResult<Option<&'py PyAny>> {
+        let Some(stream) = self.stream.clone() else {
+            return Err(PyStopAsyncIteration::new_err(()));
+        };
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            match stream.lock().await.next().await {
+                Some(Ok(info)) => Python::with_gil(|py| info_to_py(py, info)),
+                Some(Err(e)) => Err(e.into()),
+                None => Err(PyStopAsyncIteration::new_err(())),
+            }
+        })
+        .map(Some)
+    }
+}
+
+impl Drop for PlayerInfoIterator {
+    fn drop(&mut self) {
+        drop_in_runtime(self.stream.take());
+    }
+}
+
+fn info_to_py(py: Python<'_>, info: Option<HiddenInfo>) -> PyResult<PyObject> {
+    let dict = PyDict::new(py);
+    dict.set_item("hidden", info.is_none())?;
+    dict.set_item("error", py.None())?;
+    dict.set_item("transient", false)?;
+    dict.set_item("players", PyList::empty(py))?;
+    match info.flatten() {
+        Some(Ok((players, info))) => {
+            dict.set_item("players", players.as_ref())?;
+            player_info_to_py(dict, &info)?;
+        }
+        Some(Err(e)) => {
+            dict.set_item("error", e.to_string())?;
+            dict.set_item("transient", e.is_transient())?;
+        }
+        None => {}
+    }
+    Ok(dict.into())
+}
+
+fn player_info_to_py(dict: &PyDict, info: &PlayerInfo) -> PyResult<()> {
+    let py = dict.py();
+    dict.set_item("playback_status", format!("{:?}", info.playback_status))?;
+    dict.set_item(
+        "loop_status",
+        info.loop_status.map(|status| format!("{status:?}")),
+    )?;
+    dict.set_item("shuffle", info.shuffle)?;
+    let metadata = PyDict::new(py);
+    for (key, value) in info.metadata.iter() {
+        metadata.set_item(key, value_to_py(py, value))?;
+    }
+    dict.set_item("metadata", metadata)?;
+    dict.set_item("can_go_next", info.can_go_next)?;
+    dict.set_item("can_go_previous", info.can_go_previous)?;
+    dict.set_item("can_play", info.can_play)?;
+    dict.set_item("can_pause", info.can_pause)?;
+    dict.set_item("unsupported", info.unsupported.iter().collect::<Vec<_>>())?;
+    Ok(())
+}
+
+/// Converts a metadata value to the closest python type.
+fn value_to_py(py: Python<'_>, value: &Value<'_>) -> PyObject {
+    match value {
+        Value::U8(v) => v.into_py(py),
+        Value::Bool(v) => v.into_py(py),
+        Value::I16(v) => v.into_py(py),
+        Value::U16(v) => v.into_py(py),
+        Value::I32(v) => v.into_py(py),
+        Value::U32(v) => v.into_py(py),
+        Value::I64(v) => v.into_py(py),
+        Value::U64(v) => v.into_py(py),
+        Value::F64(v) => v.into_py(py),
+        Value::Str(v) => v.as_str().into_py(py),
+        Value::Signature(v) => v.as_str().into_py(py),
+        Value::ObjectPath(v) => v.as_str().into_py(py),
+        Value::Value(v) => value_to_py(py, v),
+        Value::Array(v) => PyList::new(py, v.iter().map(|v| value_to_py(py, v))).into(),
+        Value::Structure(v) => {
+            PyTuple::new(py, v.fields().iter().map(|v| value_to_py(py, v))).into()
+        }
+        Value::Dict(v) => match HashMap::<String, OwnedValue>::try_from(v.clone()) {
+            Ok(map) => map
+                .iter()
+                .map(|(key, value)| (key, value_to_py(py, value)))
+                .collect::<HashMap<_, _>>()
+                .into_py(py),
+            // only string keys are used in practice
+            Err(_) => format!("{v:?}").into_py(py),
+        },
+        Value::Fd(v) => v.to_string().into_py(py),
+    }
+}
+
+/// Follows the active player like `mpris-player-info` without the hide server.
+#[pyfunction]
+fn active_player_info() -> PlayerInfoIterator {
+    PlayerInfoIterator::new(async {
+        let conn = Connection::session().await?;
+        Ok(crate::active_player_info::active_player_info(conn)
+            .await?
+            .map(Some))
+    })
+}
+
+/// Follows the active player like `mpris-player-info`, `hidden` is set while the output is
+/// hidden through the hide server or by the auto hide rules.
+#[pyfunction]
+#[pyo3(signature = (default = false, idle = None, untitled = false))]
+fn hidden_active_player_info(
+    default: bool,
+    idle: Option<f64>,
+    untitled: bool,
+) -> PyResult<PlayerInfoIterator> {
+    let idle = idle
+        .map(Duration::try_from_secs_f64)
+        .transpose()
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let rules = AutoHide { idle, untitled };
+    Ok(PlayerInfoIterator::new(async move {
+        let conn = Connection::session().await?;
+        hidden_active_player_info_with(&conn, default, rules).await
+    }))
+}
+
+/// Controls the player with the bus name `name`, e.g. one of the `players` of an item.
+#[pyclass(module = "mpris_dbus")]
+struct Player {
+    proxy: Option<PlayerProxy<'static>>,
+}
+
+impl Player {
+    fn call<'py, F, Fut>(&self, py: Python<'py>, call: F) -> PyResult<&'py PyAny>
+    where
+        F: FnOnce(PlayerProxy<'static>) -> Fut,
+        Fut: Future<Output = zbus::Result<()>> + Send + 'static,
+    {
+        let proxy = self.proxy.clone().expect("the proxy is only taken on drop");
+        let call = call(proxy);
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            call.await.map_err(|e| PyErr::from(Error::from(e)))
+        })
+    }
+}
+
+#[pymethods]
+impl Player {
+    /// Connects to the player, must be awaited.
+    #[staticmethod]
+    fn connect(py: Python<'_>, name: String) -> PyResult<&PyAny> {
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let conn = Connection::session().await.map_err(Error::from)?;
+            let proxy = PlayerProxy::builder(&conn)
+                .destination(name)
+                .map_err(Error::from)?
+                .cache_properties(zbus::CacheProperties::No)
+                .build()
+                .await
+                .map_err(Error::from)?;
+            Ok(Player { proxy: Some(proxy) })
+        })
+    }
+
+    fn play<'py>(&self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        self.call(py, |proxy| async move { proxy.play().await })
+    }
+
+    fn pause<'py>(&self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        self.call(py, |proxy| async move { proxy.pause().await })
+    }
+
+    fn play_pause<'py>(&self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        self.call(py, |proxy| async move { proxy.play_pause().await })
+    }
+
+    fn stop<'py>(&self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        self.call(py, |proxy| async move { proxy.stop().await })
+    }
+
+    fn next<'py>(&self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        self.call(py, |proxy| async move { proxy.next().await })
+    }
+
+    fn previous<'py>(&self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        self.call(py, |proxy| async move { proxy.previous().await })
+    }
+
+    /// Seeks by `offset` microseconds.
+    fn seek<'py>(&self, py: Python<'py>, offset: i64) -> PyResult<&'py PyAny> {
+        self.call(py, |proxy| async move { proxy.seek(offset).await })
+    }
+
+    /// Sets the position in the track `track_id`, the `mpris:trackid` of the metadata.
+    fn set_position<'py>(
+        &self,
+        py: Python<'py>,
+        track_id: String,
+        position: i64,
+    ) -> PyResult<&'py PyAny> {
+        let track_id =
+            ObjectPath::try_from(track_id).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        self.call(py, |proxy| async move {
+            proxy.set_position(&track_id, position).await
+        })
+    }
+
+    fn open_uri<'py>(&self, py: Python<'py>, uri: String) -> PyResult<&'py PyAny> {
+        self.call(py, |proxy| async move { proxy.open_uri(&uri).await })
+    }
+
+    /// Sets the loop status to `"None"`, `"Track"` or `"Playlist"`.
+    fn set_loop_status<'py>(&self, py: Python<'py>, status: &str) -> PyResult<&'py PyAny> {
+        let status = match status {
+            "None" => LoopStatus::None,
+            "Track" => LoopStatus::Track,
+            "Playlist" => LoopStatus::Playlist,
+            _ => {
+                return Err(PyValueError::new_err(format!(
+                    "unknown loop status {status}"
+                )))
+            }
+        };
+        self.call(
+            py,
+            |proxy| async move { proxy.set_loop_status(status).await },
+        )
+    }
+
+    fn set_shuffle<'py>(&self, py: Python<'py>, shuffle: bool) -> PyResult<&'py PyAny> {
+        self.call(py, |proxy| async move { proxy.set_shuffle(shuffle).await })
+    }
+
+    fn set_volume<'py>(&self, py: Python<'py>, volume: f64) -> PyResult<&'py PyAny> {
+        self.call(py, |proxy| async move { proxy.set_volume(volume).await })
+    }
+
+    fn set_rate<'py>(&self, py: Python<'py>, rate: f64) -> PyResult<&'py PyAny> {
+        self.call(py, |proxy| async move { proxy.set_rate(rate).await })
+    }
+}
+
+impl Drop for Player {
+    fn drop(&mut self) {
+        drop_in_runtime(self.proxy.take());
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "mpris_dbus")]
+fn module(py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    // the nested stream futures need more than the default 2 MiB in debug builds
+    runtime.enable_all().thread_stack_size(8 << 20);
+    pyo3_asyncio::tokio::init(runtime);
+    m.add("MprisError", py.get_type::<MprisError>())?;
+    m.add_class::<PlayerInfoIterator>()?;
+    m.add_class::<Player>()?;
+    m.add_function(wrap_pyfunction!(active_player_info, m)?)?;
+    m.add_function(wrap_pyfunction!(hidden_active_player_info, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,741 @@
+//! Stream combinators used by the streams of this crate.
+//!
+//! All combinators are available on every [`Stream`] through [`StreamExt2`].
+
+use std::{
+    future::{self, Future},
+    pin::Pin,
+    task::{
+        Context,
+        Poll::{self, Pending, Ready},
+    },
+    time::Duration,
+};
+
+use pin_project_lite::pin_project;
+use tracing::Span;
+use zbus::export::futures_util::stream::{self, Chain, Once, Stream, StreamExt};
+
+use crate::util::{sleep, Sleep};
+
+/// Additional combinators for [`Stream`]s.
+pub trait StreamExt2: Stream {
+    /// Maps every item with access to an accumulator, which starts as `init`.
+    ///
+    /// The accumulator stays valid even if `map` panics, so the stream can be polled again.
+    fn fold_map<B, T, F: FnMut(Self::Item, &mut B) -> T>(
+        self,
+        init: B,
+        map: F,
+    ) -> FoldMap<B, Self, F>
+    where
+        Self: Sized,
+    {
+        FoldMap {
+            val: init,
+            fun: map,
+            stream: self,
+        }
+    }
+
+    /// Flattens a stream of streams, following only the newest inner stream.
+    ///
+    /// An inner stream is dropped as soon as the next one arrives. The stream ends once the
+    /// outer stream ends.
+    fn flatten_newest(self) -> FlattenNewest<Self, Self::Item>
+    where
+        Self: Sized,
+        Self::Item: Stream,
+    {
+        FlattenNewest { s1: self, s2: None }
+    }
+
+    /// Coalesces items arriving within `window` of the first one, yielding only the last.
+    /// A zero `window` yields every item as is.
+    fn coalesce(self, window: Duration) -> Coalesce<Self>
+    where
+        Self: Sized,
+    {
+        Coalesce {
+            stream: self,
+            window,
+            pending: None,
+            timer: None,
+            done: false,
+        }
+    }
+
+    /// Yields `val` before the items of the stream.
+    fn with_initial_value(self, val: Self::Item) -> Chain<Once<future::Ready<Self::Item>>, Self>
+    where
+        Self: Sized,
+    {
+        stream::once(future::ready(val)).chain(self)
+    }
+
+    /// Polls the stream inside of `span`.
+    fn instrument_stream(self, span: Span) -> InstrumentedStream<Self>
+    where
+        Self: Sized,
+    {
+        InstrumentedStream { s: self, span }
+    }
+
+    /// Drops items equal to the previous one.
+    fn filter_no_change(self) -> FilterNoChange<Self>
+    where
+        Self: Sized,
+        Self::Item: Clone + PartialEq,
+    {
+        FilterNoChange {
+            stream: self,
+            last: None,
+        }
+    }
+}
+
+impl<S: Stream> StreamExt2 for S {}
+
+pin_project! {
+    /// Stream returned by [`StreamExt2::fold_map`].
+    pub struct FoldMap<B, S, F> {
+        val: B,
+        fun: F,
+        #[pin]
+        stream: S,
+    }
+}
+
+impl<B, S: Stream, T, F: FnMut(S::Item, &mut B) -> T> Stream for FoldMap<B, S, F> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        match this.stream.poll_next(cx) {
+            Pending => Pending,
+            Ready(None) => Ready(None),
+            Ready(Some(next)) => Ready(Some((this.fun)(next, this.val))),
+        }
+    }
+}
+
+pin_project! {
+    /// Stream returned by [`StreamExt2::flatten_newest`].
+    pub struct FlattenNewest<S1, S2> {
+        #[pin]
+        s1: S1,
+        #[pin]
+        s2: Option<S2>,
+    }
+}
+
+impl<S1, S2> Stream for FlattenNewest<S1, S2>
+where
+    S1: Stream<Item = S2>,
+    S2: Stream,
+{
+    type Item = S2::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            match this.s1.as_mut().poll_next(cx) {
+                Ready(None) => {
+                    return Ready(None);
+                }
+                Ready(Some(s2)) => {
+                    this.s2.set(Some(s2));
+                }
+                Pending => {}
+            }
+            match this.s2.as_mut().as_pin_mut() {
+                Some(s2) => match s2.poll_next(cx) {
+                    Ready(None) => {
+                        this.s2.set(None);
+                    }
+                    r => return r,
+                },
+                None => return Pending,
+            };
+        }
+    }
+}
+
+/// When a stream created by [`combine_latest`] ends.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Termination {
+    /// End as soon as any of the streams ends.
+    #[default]
+    Any,
+    /// End once all streams have ended, repeating the last items of the ended ones.
+    All,
+}
+
+/// Tuples of streams that can be combined with [`combine_latest`].
+pub trait CombineLatest {
+    type Stream: Stream;
+
+    fn combine_latest(self, termination: Termination) -> Self::Stream;
+}
+
+/// Yields the latest items of all `streams` whenever one of them yields, `None` for streams
+/// which have not yielded yet.
+///
+/// Every call polls each running stream once, so every item of the streams is part of at least
+/// one yielded tuple. `streams` is a tuple of two to six streams.
+pub fn combine_latest<T: CombineLatest>(streams: T, termination: Termination) -> T::Stream {
+    streams.combine_latest(termination)
+}
+
+macro_rules! combine_latest {
+    ($name:ident; $(($S:ident, $s:ident, $v:ident, $done:ident)),+) => {
+        pin_project! {
+            /// Stream returned by [`combine_latest`].
+            pub struct $name<$($S: Stream),+> {
+                $(
+                    #[pin]
+                    $s: $S,
+                    $v: Option<$S::Item>,
+                    $done: bool,
+                )+
+                termination: Termination,
+                finished: bool,
+            }
+        }
+
+        impl<$($S: Stream),+> Stream for $name<$($S),+>
+        where
+            $($S::Item: Clone),+
+        {
+            type Item = ($(Option<$S::Item>),+);
+
+            fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+                let this = self.project();
+                if *this.finished {
+                    return Ready(None);
+                }
+                let mut changed = false;
+                let mut ended = false;
+                $(
+                    if !*this.$done {
+                        match this.$s.poll_next(cx) {
+                            Ready(Some(v)) => {
+                                *this.$v = Some(v);
+                                changed = true;
+                            }
+                            Ready(None) => {
+                                *this.$done = true;
+                                ended = true;
+                            }
+                            Pending => {}
+                        }
+                    }
+                )+
+                // items received together with the end are still yielded
+                *this.finished = $(*this.$done)&&+
+                    || (ended && *this.termination == Termination::Any);
+                if changed {
+                    Ready(Some(($(this.$v.clone()),+)))
+                } else if *this.finished {
+                    Ready(None)
+                } else {
+                    Pending
+                }
+            }
+        }
+
+        impl<$($S: Stream),+> CombineLatest for ($($S),+)
+        where
+            $($S::Item: Clone),+
+        {
+            type Stream = $name<$($S),+>;
+
+            fn combine_latest(self, termination: Termination) -> Self::Stream {
+                let ($($s),+) = self;
+                $name {
+                    $(
+                        $s,
+                        $v: None,
+                        $done: false,
+                    )+
+                    termination,
+                    finished: false,
+                }
+            }
+        }
+    };
+}
+
+combine_latest!(CombineLatest2; (S1, s1, v1, done1), (S2, s2, v2, done2));
+combine_latest!(CombineLatest3; (S1, s1, v1, done1), (S2, s2, v2, done2), (S3, s3, v3, done3));
+combine_latest!(
+    CombineLatest4;
+    (S1, s1, v1, done1),
+    (S2, s2, v2, done2),
+    (S3, s3, v3, done3),
+    (S4, s4, v4, done4)
+);
+combine_latest!(
+    CombineLatest5;
+    (S1, s1, v1, done1),
+    (S2, s2, v2, done2),
+    (S3, s3, v3, done3),
+    (S4, s4, v4, done4),
+    (S5, s5, v5, done5)
+);
+combine_latest!(
+    CombineLatest6;
+    (S1, s1, v1, done1),
+    (S2, s2, v2, done2),
+    (S3, s3, v3, done3),
+    (S4, s4, v4, done4),
+    (S5, s5, v5, done5),
+    (S6, s6, v6, done6)
+);
+
+pin_project! {
+    /// Stream returned by [`StreamExt2::coalesce`].
+    pub struct Coalesce<S: Stream> {
+        #[pin]
+        stream: S,
+        window: Duration,
+        pending: Option<S::Item>,
+        #[pin]
+        timer: Option<Sleep>,
+        // the inner stream ended, it must not be polled again
+        done: bool,
+    }
+}
+
+impl<S: Stream> Stream for Coalesce<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        if *this.done {
+            return Ready(None);
+        }
+        loop {
+            match this.stream.as_mut().poll_next(cx) {
+                Ready(Some(v)) if this.window.is_zero() => return Ready(Some(v)),
+                Ready(Some(v)) => {
+                    if this.timer.is_none() {
+                        this.timer.set(Some(sleep(*this.window)));
+                    }
+                    *this.pending = Some(v);
+                }
+                // flush the last item before ending
+                Ready(None) => {
+                    *this.done = true;
+                    this.timer.set(None);
+                    return Ready(this.pending.take());
+                }
+                Pending => break,
+            }
+        }
+        match this.timer.as_mut().as_pin_mut().map(|timer| timer.poll(cx)) {
+            Some(Ready(())) => {
+                this.timer.set(None);
+                match this.pending.take() {
+                    Some(v) => Ready(Some(v)),
+                    None => Pending,
+                }
+            }
+            _ => Pending,
+        }
+    }
+}
+
+pin_project! {
+    /// Stream returned by [`StreamExt2::instrument_stream`].
+    pub struct InstrumentedStream<S> {
+        #[pin]
+        s: S,
+        span: Span,
+    }
+}
+
+impl<S: Stream> Stream for InstrumentedStream<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        this.span.in_scope(|| this.s.poll_next(cx))
+    }
+}
+
+pin_project! {
+    /// Stream returned by [`StreamExt2::filter_no_change`].
+    pub struct FilterNoChange<S: Stream> {
+        #[pin]
+        stream: S,
+        last: Option<S::Item>,
+    }
+}
+
+impl<S: Stream> Stream for FilterNoChange<S>
+where
+    S::Item: Clone + PartialEq,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            break match this.stream.as_mut().poll_next(cx) {
+                Ready(Some(v)) if this.last.as_ref() == Some(&v) => continue,
+                Ready(Some(v)) => {
+                    *this.last = Some(v.clone());
+                    Ready(Some(v))
+                }
+                Ready(None) => {
+                    *this.last = None;
+                    Ready(None)
+                }
+                Pending => Pending,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    //! Apart from `coalesce`, the tests poll with a no-op waker and scripted streams, so they
+    //! need no runtime and also run under miri.
+
+    use std::{
+        cell::RefCell,
+        collections::VecDeque,
+        future::{poll_fn, Future},
+        panic::{catch_unwind, AssertUnwindSafe},
+        pin::{pin, Pin},
+        rc::Rc,
+        task::{
+            Context,
+            Poll::{self, Pending, Ready},
+        },
+    };
+
+    use super::{StreamExt2, Termination};
+    use proptest::prelude::*;
+    use tokio::sync::mpsc;
+    use tokio_stream::wrappers::ReceiverStream;
+    use tracing::Span;
+    use zbus::export::futures_util::{
+        stream::{self, Stream, StreamExt},
+        task::noop_waker_ref,
+    };
+
+    macro_rules! assert_pending {
+        ($f:expr, $s:literal) => {
+            let mut f = $f;
+            poll_fn(|cx| {
+                assert!(
+                    matches!(Future::poll(Pin::as_mut(&mut f), cx), Poll::Pending),
+                    $s
+                );
+                Poll::Ready(())
+            })
+            .await
+        };
+    }
+
+    /// A stream yielding the pushed results, `Pending` while there are none.
+    #[derive(Clone)]
+    struct Script<T>(Rc<RefCell<VecDeque<Poll<Option<T>>>>>);
+
+    impl<T> Script<T> {
+        fn new() -> Self {
+            Self(Rc::new(RefCell::new(VecDeque::new())))
+        }
+
+        fn push(&self, item: T) {
+            self.0.borrow_mut().push_back(Ready(Some(item)));
+        }
+
+        fn end(&self) {
+            self.0.borrow_mut().push_back(Ready(None));
+        }
+
+        fn stream(&self) -> impl Stream<Item = T> {
+            let script = self.0.clone();
+            stream::poll_fn(move |_| script.borrow_mut().pop_front().unwrap_or(Pending))
+        }
+    }
+
+    fn poll<S: Stream>(s: Pin<&mut S>) -> Poll<Option<S::Item>> {
+        s.poll_next(&mut Context::from_waker(noop_waker_ref()))
+    }
+
+    #[test]
+    fn fold_map() {
+        let script = Script::new();
+        let mut s = pin!(script.stream().fold_map(0, |v: u8, sum: &mut u8| {
+            *sum = sum.checked_add(v).expect("overflow");
+            *sum
+        }));
+        assert_eq!(Pending, poll(s.as_mut()), "no items");
+        script.push(1);
+        script.push(2);
+        assert_eq!(Ready(Some(1)), poll(s.as_mut()), "first sum");
+        assert_eq!(Ready(Some(3)), poll(s.as_mut()), "second sum");
+        script.push(255);
+        assert!(
+            catch_unwind(AssertUnwindSafe(|| poll(s.as_mut()))).is_err(),
+            "map function panicked"
+        );
+        script.push(4);
+        assert_eq!(Ready(Some(7)), poll(s.as_mut()), "usable after a panic");
+        script.end();
+        assert_eq!(Ready(None), poll(s.as_mut()), "ended");
+    }
+
+    #[test]
+    fn flatten_newest() {
+        let outer = Script::new();
+        let (inner1, inner2) = (Script::new(), Script::new());
+        let mut s = pin!(outer.stream().flatten_newest());
+        assert_eq!(Pending, poll(s.as_mut()), "no inner stream");
+        outer.push(inner1.stream());
+        inner1.push(1);
+        assert_eq!(Ready(Some(1)), poll(s.as_mut()), "first inner stream");
+        inner1.push(2);
+        outer.push(inner2.stream());
+        inner2.push(3);
+        assert_eq!(Ready(Some(3)), poll(s.as_mut()), "replaced inner stream");
+        inner2.end();
+        assert_eq!(Pending, poll(s.as_mut()), "inner stream ended");
+        outer.end();
+        assert_eq!(Ready(None), poll(s.as_mut()), "outer stream ended");
+    }
+
+    #[test]
+    fn filter_no_change() {
+        let mut stream = pin!(stream::iter([1u8, 1, 2, 1]).filter_no_change());
+        assert_eq!(Ready(Some(1)), poll(stream.as_mut()), "first value");
+        assert_eq!(
+            Ready(Some(2)),
+            poll(stream.as_mut()),
+            "repeated value dropped"
+        );
+        assert_eq!(
+            Ready(Some(1)),
+            poll(stream.as_mut()),
+            "old value after a change"
+        );
+        assert_eq!(Ready(None), poll(stream.as_mut()), "ended");
+    }
+
+    #[test]
+    fn with_initial_value_and_instrument_stream() {
+        let mut stream = pin!(stream::iter([2u8])
+            .with_initial_value(1)
+            .instrument_stream(Span::none()));
+        assert_eq!(Ready(Some(1)), poll(stream.as_mut()), "initial value");
+        assert_eq!(Ready(Some(2)), poll(stream.as_mut()), "stream value");
+        assert_eq!(Ready(None), poll(stream.as_mut()), "ended");
+    }
+
+    #[tokio::test]
+    async fn combine_latest() {
+        let (in1, stream1) = mpsc::channel::<u8>(4);
+        let (in2, stream2) = mpsc::channel::<i8>(4);
+        let mut s = pin!(super::combine_latest(
+            (ReceiverStream::new(stream1), ReceiverStream::new(stream2)),
+            Termination::Any
+        ));
+        assert_pending!(pin!(s.next()), "both streams empty");
+        in1.send(255).await.unwrap();
+        assert_eq!(
+            Some((Some(255), None)),
+            s.next().await,
+            "streams partially filled"
+        );
+        assert_pending!(pin!(s.next()), "already polled");
+        in2.send(-1).await.unwrap();
+        assert_eq!(
+            Some((Some(255), Some(-1))),
+            s.next().await,
+            "streams filled"
+        );
+        assert_pending!(pin!(s.next()), "already polled");
+        in1.send(244).await.unwrap();
+        assert_eq!(
+            Some((Some(244), Some(-1))),
+            s.next().await,
+            "value changed 1"
+        );
+        assert_pending!(pin!(s.next()), "already polled");
+        in2.send(-2).await.unwrap();
+        assert_eq!(
+            Some((Some(244), Some(-2))),
+            s.next().await,
+            "value changed 2"
+        );
+        assert_pending!(pin!(s.next()), "already polled");
+        drop(in1);
+        assert_eq!(None, s.next().await, "stream 1 closed");
+
+        let (in1, stream1) = mpsc::channel::<u8>(4);
+        let (in2, stream2) = mpsc::channel::<i8>(4);
+        let mut s = pin!(super::combine_latest(
+            (ReceiverStream::new(stream1), ReceiverStream::new(stream2)),
+            Termination::Any
+        ));
+
+        assert_pending!(pin!(s.next()), "both streams empty");
+        in2.send(-1).await.unwrap();
+        assert_eq!(
+            Some((None, Some(-1))),
+            s.next().await,
+            "streams partially filled"
+        );
+        assert_pending!(pin!(s.next()), "already polled");
+        in1.send(255).await.unwrap();
+        assert_eq!(
+            Some((Some(255), Some(-1))),
+            s.next().await,
+            "streams filled"
+        );
+        assert_pending!(pin!(s.next()), "already polled");
+        drop(in2);
+        assert_eq!(None, s.next().await, "stream 2 closed");
+    }
+
+    #[test]
+    fn combine_latest_all() {
+        let (script1, script2, script3) = (Script::new(), Script::new(), Script::new());
+        let mut s = pin!(super::combine_latest(
+            (script1.stream(), script2.stream(), script3.stream()),
+            Termination::All
+        ));
+        script1.push(1);
+        script2.push(2);
+        assert_eq!(Ready(Some((Some(1), Some(2), None))), poll(s.as_mut()), "first items");
+        script1.end();
+        script3.push(3);
+        assert_eq!(
+            Ready(Some((Some(1), Some(2), Some(3)))),
+            poll(s.as_mut()),
+            "item received with an end"
+        );
+        assert_eq!(Pending, poll(s.as_mut()), "other streams still running");
+        script2.end();
+        script3.push(4);
+        assert_eq!(Ready(Some((Some(1), Some(2), Some(4)))), poll(s.as_mut()), "last item");
+        script3.end();
+        assert_eq!(Ready(None), poll(s.as_mut()), "all streams ended");
+        assert_eq!(Ready(None), poll(s.as_mut()), "stays ended");
+    }
+
+    /// What a scripted stream does when it is polled.
+    #[derive(Debug, Clone)]
+    enum Step {
+        Item,
+        Pending,
+        End,
+    }
+
+    fn steps() -> impl Strategy<Value = Vec<Step>> {
+        prop::collection::vec(
+            prop_oneof![4 => Just(Step::Item), 4 => Just(Step::Pending), 1 => Just(Step::End)],
+            0..20,
+        )
+    }
+
+    /// Scripts a stream yielding increasing numbers according to `steps`, followed by its end.
+    fn script(steps: &[Step]) -> (Script<usize>, Vec<usize>) {
+        let script = Script::new();
+        let mut items = Vec::new();
+        for step in steps {
+            match step {
+                Step::Item => {
+                    script.push(items.len());
+                    items.push(items.len());
+                }
+                Step::Pending => script.0.borrow_mut().push_back(Pending),
+                Step::End => break,
+            }
+        }
+        script.end();
+        (script, items)
+    }
+
+    /// The distinct values of one component of the yielded tuples.
+    fn changes(values: impl Iterator<Item = Option<usize>>) -> Vec<usize> {
+        let mut changes: Vec<usize> = Vec::new();
+        for value in values.flatten() {
+            if changes.last() != Some(&value) {
+                changes.push(value);
+            }
+        }
+        changes
+    }
+
+    proptest! {
+        #[test]
+        fn combine_latest_items(steps1 in steps(), steps2 in steps(), any in any::<bool>()) {
+            let (script1, items1) = script(&steps1);
+            let (script2, items2) = script(&steps2);
+            let termination = if any { Termination::Any } else { Termination::All };
+            let mut s = pin!(super::combine_latest(
+                (script1.stream(), script2.stream()),
+                termination
+            ));
+            let mut yielded = Vec::new();
+            // every step is consumed by a poll, so the streams have ended after that many polls
+            for _ in 0..steps1.len() + steps2.len() + 2 {
+                match poll(s.as_mut()) {
+                    Ready(Some(items)) => yielded.push(items),
+                    Ready(None) => break,
+                    Pending => {}
+                }
+            }
+            prop_assert_eq!(Ready(None), poll(s.as_mut()), "stream ended");
+            let changes1 = changes(yielded.iter().map(|items| items.0));
+            let changes2 = changes(yielded.iter().map(|items| items.1));
+            match termination {
+                Termination::All => {
+                    prop_assert_eq!(&items1, &changes1, "all items of stream 1");
+                    prop_assert_eq!(&items2, &changes2, "all items of stream 2");
+                }
+                Termination::Any => {
+                    prop_assert!(items1.starts_with(&changes1), "items of stream 1 in order");
+                    prop_assert!(items2.starts_with(&changes2), "items of stream 2 in order");
+                    prop_assert!(
+                        changes1 == items1 || changes2 == items2,
+                        "ended after one of the streams"
+                    );
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(start_paused = true)]
+    async fn coalesce() {
+        use std::{future::ready, time::Duration};
+
+        let (input, stream) = mpsc::channel::<u8>(4);
+        let mut s = pin!(ReceiverStream::new(stream).coalesce(Duration::from_millis(30)));
+        input.send(1).await.unwrap();
+        input.send(2).await.unwrap();
+        assert_eq!(Some(2), s.next().await, "burst coalesced");
+        assert_pending!(pin!(s.next()), "already yielded");
+        input.send(3).await.unwrap();
+        drop(input);
+        assert_eq!(Some(3), s.next().await, "pending item flushed");
+        assert_eq!(None, s.next().await, "stream ended");
+
+        let mut s = pin!(stream::iter([1u8, 2]).coalesce(Duration::ZERO));
+        assert_eq!(Some(1), s.next().await, "zero window");
+        assert_eq!(Some(2), s.next().await, "zero window");
+
+        // unfold panics if it is polled after its end
+        let unfold = stream::unfold(0u8, |n| ready((n < 2).then_some((n, n + 1))));
+        let mut s = pin!(unfold.coalesce(Duration::from_millis(30)));
+        assert_eq!(Some(1), s.next().await, "flushed at the end");
+        assert_eq!(None, s.next().await, "ended");
+        assert_eq!(None, s.next().await, "still ended");
+    }
+}
@@ -0,0 +1,27 @@
+//! Fixtures shared by the tests of the library and the binary.
+
+use std::{collections::HashMap, sync::Arc};
+
+use zbus::zvariant::Value;
+
+use crate::{player_info::PlayerInfo, proxies::PlaybackStatus};
+
+/// The info of a player without any capabilities.
+pub fn player_info(playback_status: PlaybackStatus, metadata: &[(&str, Value)]) -> PlayerInfo {
+    PlayerInfo {
+        playback_status,
+        loop_status: None,
+        shuffle: None,
+        metadata: Arc::new(
+            metadata
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.clone().into()))
+                .collect::<HashMap<_, _>>(),
+        ),
+        can_go_next: false,
+        can_go_previous: false,
+        can_play: false,
+        can_pause: false,
+        unsupported: Default::default(),
+    }
+}